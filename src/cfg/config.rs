@@ -2,11 +2,11 @@
 
 use serde::Deserialize;
 
-pub use crate::cfg::otto::{default_otto, Otto};
-pub use crate::cfg::task::{deserialize_task_map, Task, Tasks};
+pub use crate::cfg::otto::{default_otto, Otto, OutputMode};
+pub use crate::cfg::task::{deserialize_task_map, Dep, Task, Tasks};
 pub use crate::cfg::param::{Param, Params, Value};
 
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 pub struct Config {
     #[serde(default = "default_otto")]
     pub otto: Otto,
@@ -14,12 +14,3 @@ pub struct Config {
     #[serde(default, deserialize_with = "deserialize_task_map")]
     pub tasks: Tasks,
 }
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            otto: default_otto(),
-            tasks: Tasks::new(),
-        }
-    }
-}
@@ -1,8 +1,15 @@
 //#![allow(unused_imports, unused_variables, dead_code)]
 
 use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use std::vec::Vec;
 
+use crate::cfg::task::deserialize_duration;
+use crate::cmd::script::DEFAULT_SHELL;
+
 fn default_name() -> String {
     "otto".to_string()
 }
@@ -31,6 +38,14 @@ fn default_verbosity() -> String {
     "1".to_string()
 }
 
+fn default_shell() -> String {
+    DEFAULT_SHELL.to_string()
+}
+
+const fn default_grace_period() -> Duration {
+    Duration::from_secs(5)
+}
+
 #[must_use]
 pub fn default_otto() -> Otto {
     Otto {
@@ -41,6 +56,49 @@ pub fn default_otto() -> Otto {
         home: default_home(),
         tasks: default_tasks(),
         verbosity: default_verbosity(),
+        keep_going: false,
+        output: OutputMode::default(),
+        grace_period: default_grace_period(),
+        shell: default_shell(),
+        dry_run: false,
+        env_prefix: String::new(),
+        invocation_dir: None,
+    }
+}
+
+/// How the output of concurrently running tasks is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Lines are printed as they are produced, prefixed with the task name.
+    #[default]
+    Interleaved,
+    /// A task's lines are printed together once it finishes.
+    Grouped,
+    /// Only the output of failed tasks is printed.
+    Quiet,
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interleaved => write!(f, "interleaved"),
+            Self::Grouped => write!(f, "grouped"),
+            Self::Quiet => write!(f, "quiet"),
+        }
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interleaved" => Ok(Self::Interleaved),
+            "grouped" => Ok(Self::Grouped),
+            "quiet" => Ok(Self::Quiet),
+            _ => Err(format!("unknown output mode: {s}")),
+        }
     }
 }
 
@@ -66,6 +124,31 @@ pub struct Otto {
 
     #[serde(default = "default_verbosity")]
     pub verbosity: String,
+
+    #[serde(default)]
+    pub keep_going: bool,
+
+    #[serde(default)]
+    pub output: OutputMode,
+
+    /// How long tasks get to exit after an interrupt is forwarded to them before they are killed.
+    #[serde(default = "default_grace_period", deserialize_with = "deserialize_duration")]
+    pub grace_period: Duration,
+
+    /// The interpreter for actions that have no shebang and whose task sets no shell of its own.
+    #[serde(default = "default_shell", alias = "interpreter")]
+    pub shell: String,
+
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// What the environment variables of task parameters start with, such as `OTTO_ARG_`.
+    #[serde(default)]
+    pub env_prefix: String,
+
+    /// Where otto counts as invoked from, if not the current directory. Set with `--invocation-dir`.
+    #[serde(skip)]
+    pub invocation_dir: Option<PathBuf>,
 }
 
 impl Default for Otto {
@@ -2,7 +2,7 @@
 
 use eyre::Result;
 use serde::de::{Deserializer, Error, MapAccess, SeqAccess, Visitor};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::vec::Vec;
@@ -46,37 +46,44 @@ pub struct Param {
 
     #[serde(skip_deserializing)]
     pub value: Value,
+
+    /// Where the parameter appears among the params of its task, which is the order positionals
+    /// are taken in
+    #[serde(skip_deserializing)]
+    pub order: usize,
+}
+
+impl Param {
+    /// The name the value of the parameter is kept under, which is what `cmd` placeholders call it
+    /// and, after the configured prefix, its variable in the task's environment: its `dest` if it
+    /// has one, or else its long name, short name or positional name, with dashes turned into
+    /// underscores.
+    #[must_use]
+    pub fn dest(&self) -> String {
+        self.dest.as_deref().unwrap_or(&self.name).replace('-', "_")
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum ParamType {
     FLG,
+    #[default]
     OPT,
     POS,
 }
 
-impl Default for ParamType {
-    fn default() -> Self {
-        Self::OPT
-    }
-}
-
 pub type Values = HashMap<String, Value>;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
 pub enum Value {
     Item(String),
     List(Vec<String>),
     Dict(HashMap<String, String>),
+    #[default]
     Empty,
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Self::Empty
-    }
-}
-
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -95,6 +102,16 @@ impl fmt::Display for Value {
     }
 }
 
+/// A value is written as a string, which numbers and booleans are taken as, or a list of them.
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_value(deserializer)
+    }
+}
+
 fn deserialize_value<'de, D>(deserializer: D) -> Result<Value, D::Error>
 where
     D: Deserializer<'de>,
@@ -112,12 +129,39 @@ where
         {
             Ok(Value::Item(value.to_owned()))
         }
+        fn visit_bool<E>(self, value: bool) -> Result<Self::Value, E>
+        where
+            E: Error,
+        {
+            Ok(Value::Item(value.to_string()))
+        }
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: Error,
+        {
+            Ok(Value::Item(value.to_string()))
+        }
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: Error,
+        {
+            Ok(Value::Item(value.to_string()))
+        }
+        fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+        where
+            E: Error,
+        {
+            Ok(Value::Item(value.to_string()))
+        }
         fn visit_seq<S>(self, mut visitor: S) -> Result<Self::Value, S::Error>
         where
             S: SeqAccess<'de>,
         {
             let mut vec: Vec<String> = vec![];
-            while let Some(item) = visitor.next_element()? {
+            while let Some(item) = visitor.next_element::<Value>()? {
+                let Value::Item(item) = item else {
+                    return Err(S::Error::custom("a list can only hold strings, numbers and booleans"));
+                };
                 vec.push(item);
             }
             Ok(Value::List(vec))
@@ -125,8 +169,11 @@ where
     }
     deserializer.deserialize_any(ValueEnum)
 }
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// How many values a parameter takes, as in argparse: `1`, `0`, `?`, `+`, `*`, a count such as
+/// `3`, or a range such as `2:4`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum Nargs {
+    #[default]
     One,
     Zero,
     OneOrZero,
@@ -135,9 +182,12 @@ pub enum Nargs {
     Range(usize, usize),
 }
 
-impl Default for Nargs {
-    fn default() -> Self {
-        Self::One
+impl Nargs {
+    /// Whether the values of the parameter are collected into a [`Value::List`], rather than
+    /// being a single [`Value::Item`].
+    #[must_use]
+    pub const fn is_list(&self) -> bool {
+        matches!(self, Self::OneOrMore | Self::ZeroOrMore | Self::Range(..))
     }
 }
 
@@ -149,7 +199,7 @@ impl fmt::Display for Nargs {
             Self::OneOrZero => write!(formatter, "Nargs::OneOrZero[?]"),
             Self::OneOrMore => write!(formatter, "Nargs::OneOrMore[+]"),
             Self::ZeroOrMore => write!(formatter, "Nargs::ZeroOrMore[*]"),
-            Self::Range(min, max) => write!(formatter, "Nargs::Range[{}, {}]", min + 1, max),
+            Self::Range(min, max) => write!(formatter, "Nargs::Range[{min}, {max}]"),
         }
     }
 }
@@ -159,23 +209,36 @@ impl<'de> Deserialize<'de> for Nargs {
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        let result = match &s[..] {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Spec {
+            Count(usize),
+            Text(String),
+        }
+
+        let s = match Spec::deserialize(deserializer)? {
+            Spec::Count(count) => count.to_string(),
+            Spec::Text(text) => text,
+        };
+        let result = match s.trim() {
             "1" => Self::One,
             "0" => Self::Zero,
             "?" => Self::OneOrZero,
             "+" => Self::OneOrMore,
             "*" => Self::ZeroOrMore,
             _ => {
-                println!("s={s}");
-                if s.contains(':') {
-                    let parts: Vec<&str> = s.split(':').collect();
-                    let min: usize = parts[0].parse().map_err(Error::custom)?;
-                    let max: usize = parts[1].parse().map_err(Error::custom)?;
-                    Self::Range(min - 1, max)
+                let (min, max) = match s.split_once(':') {
+                    Some((min, max)) => (min.trim().parse(), max.trim().parse()),
+                    None => (s.trim().parse(), s.trim().parse()),
+                };
+                let (min, max): (usize, usize) = (min.map_err(Error::custom)?, max.map_err(Error::custom)?);
+                if min > max {
+                    return Err(Error::custom(format!("invalid nargs {s}: {min} is more than {max}")));
+                }
+                if max == 0 {
+                    Self::Zero
                 } else {
-                    let num = s.parse().map_err(Error::custom)?;
-                    Self::Range(0, num)
+                    Self::Range(min, max)
                 }
             }
         };
@@ -187,8 +250,8 @@ fn divine(title: &str) -> (String, Option<char>, Option<String>) {
     let flags: Vec<String> = title.split('|').map(std::string::ToString::to_string).collect();
     let short = flags
         .iter()
-        .cloned()
         .filter(|i| i.starts_with('-') && i.len() == 2)
+        .cloned()
         .collect::<String>()
         .trim_matches('-')
         .chars()
@@ -197,8 +260,8 @@ fn divine(title: &str) -> (String, Option<char>, Option<String>) {
     let long = Some(String::from(
         flags
             .iter()
-            .cloned()
             .filter(|i| i.starts_with("--") && i.len() > 2)
+            .cloned()
             .collect::<String>()
             .trim_matches('-'),
     ))
@@ -232,12 +295,16 @@ where
             let mut params = Params::new();
             while let Some((title, mut param)) = map.next_entry::<String, Param>()? {
                 (param.name, param.short, param.long) = divine(&title);
+                param.order = params.len();
                 if param.long.is_some() || param.short.is_some() {
-                    if let Some(ref value) = param.default {
-                        if value == "true" || value == "false" {
-                            param.param_type = ParamType::FLG;
-                        }
+                    let boolean = matches!(param.default.as_deref(), Some("true" | "false"));
+                    if param.nargs == Nargs::Zero || (param.nargs == Nargs::One && boolean) {
+                        // A flag is a switch, so it takes no values
+                        param.param_type = ParamType::FLG;
+                        param.nargs = Nargs::Zero;
                     }
+                } else if param.nargs == Nargs::Zero {
+                    return Err(M::Error::custom(format!("positional param {title} must take a value")));
                 } else {
                     param.param_type = ParamType::POS;
                 }
@@ -248,3 +315,52 @@ where
     }
     deserializer.deserialize_map(ParamMap)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nargs() {
+        let nargs = |s: &str| serde_yaml::from_str::<Nargs>(&format!("'{s}'"));
+        assert_eq!(nargs("1").unwrap(), Nargs::One);
+        assert_eq!(nargs("0").unwrap(), Nargs::Zero);
+        assert_eq!(nargs("?").unwrap(), Nargs::OneOrZero);
+        assert_eq!(nargs("+").unwrap(), Nargs::OneOrMore);
+        assert_eq!(nargs("*").unwrap(), Nargs::ZeroOrMore);
+        assert_eq!(nargs("3").unwrap(), Nargs::Range(3, 3));
+        assert_eq!(nargs("2:4").unwrap(), Nargs::Range(2, 4));
+        assert!(nargs("4:2").is_err());
+        assert!(nargs("many").is_err());
+        assert_eq!(serde_yaml::from_str::<Nargs>("2").unwrap(), Nargs::Range(2, 2));
+        assert!(!Nargs::OneOrZero.is_list());
+        assert!(Nargs::Range(2, 4).is_list());
+    }
+
+    #[test]
+    fn test_param_types() {
+        let yaml = r#"
+-v|--verbose:
+  default: "false"
+-c|--color:
+  nargs: 0
+  constant: always
+-o|--out:
+  default: "true"
+  nargs: "?"
+src: {}
+dst:
+  nargs: "+"
+"#;
+        let params = deserialize_param_map(serde_yaml::Deserializer::from_str(yaml)).unwrap();
+        assert_eq!(params["-v|--verbose"].param_type, ParamType::FLG);
+        assert_eq!(params["-v|--verbose"].nargs, Nargs::Zero);
+        assert_eq!(params["-c|--color"].param_type, ParamType::FLG);
+        assert_eq!(params["-o|--out"].param_type, ParamType::OPT);
+        assert_eq!(params["src"].param_type, ParamType::POS);
+        assert_eq!(params["dst"].param_type, ParamType::POS);
+        assert_eq!(params["dst"].order, 4);
+
+        assert!(deserialize_param_map(serde_yaml::Deserializer::from_str("src:\n  nargs: 0\n")).is_err());
+    }
+}
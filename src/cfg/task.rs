@@ -1,17 +1,144 @@
 //#![allow(unused_imports, unused_variables, dead_code)]
 
-use eyre::Result;
-use serde::de::{Deserializer, MapAccess, Visitor};
+use eyre::{eyre, Result};
+use serde::de::{Deserializer, Error, MapAccess, Visitor};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::time::Duration;
 use std::vec::Vec;
 
-use crate::cfg::param::{deserialize_param_map, Params};
+use crate::cfg::param::{deserialize_param_map, Params, Value};
 
 pub type Tasks = HashMap<String, Task>;
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+fn default_show() -> bool {
+    true
+}
+
+fn default_cache() -> bool {
+    true
+}
+
+/// How long to wait before the first retry of a failed task; the delay doubles with every retry.
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Parses a duration such as `90`, `1.5s`, `500ms`, `10m` or `2h`; a bare number is in seconds.
+///
+/// # Errors
+///
+/// This function will return an error if `duration` is not a number with an optional ms, s, m or h suffix.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let digits = duration.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &duration[digits.len()..] {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(eyre!("invalid duration: {}", duration)),
+    };
+    let number: f64 = digits.trim().parse().map_err(|_| eyre!("invalid duration: {}", duration))?;
+    Duration::try_from_secs_f64(number * multiplier).map_err(|_| eyre!("invalid duration: {}", duration))
+}
+
+/// Formats `duration` the way [`parse_duration`] reads it, in the largest unit that holds it exactly,
+/// such as `5s`, `500ms` or `2h`.
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if !duration.subsec_nanos().is_multiple_of(1_000_000) {
+        format!("{}s", duration.as_secs_f64())
+    } else if millis == 0 {
+        "0s".to_string()
+    } else if millis.is_multiple_of(3_600_000) {
+        format!("{}h", millis / 3_600_000)
+    } else if millis.is_multiple_of(60_000) {
+        format!("{}m", millis / 60_000)
+    } else if millis.is_multiple_of(1000) {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{millis}ms")
+    }
+}
+
+/// Deserializes a duration given as a number of seconds or as a string understood by [`parse_duration`].
+///
+/// # Errors
+///
+/// This function will return an error if the value is neither a non-negative number nor a valid duration string.
+pub fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Spec {
+        Seconds(f64),
+        Text(String),
+    }
+
+    match Spec::deserialize(deserializer)? {
+        Spec::Seconds(secs) => Duration::try_from_secs_f64(secs).map_err(D::Error::custom),
+        Spec::Text(text) => parse_duration(&text).map_err(D::Error::custom),
+    }
+}
+
+/// A task that another one requires or is ordered after: just its name, so it runs with its
+/// defaults, or `{task: build, args: {profile: release}}` for it to run with those values for its
+/// params. An `optional` one is left out if there is no such task.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dep {
+    pub task: String,
+    /// Values by param name, or dest
+    pub args: BTreeMap<String, Value>,
+    pub optional: bool,
+}
+
+impl Dep {
+    #[must_use]
+    pub fn new(task: &str) -> Self {
+        Self {
+            task: task.to_string(),
+            args: BTreeMap::new(),
+            optional: false,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Dep {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Spec {
+            Name(String),
+            Task {
+                task: String,
+                #[serde(default)]
+                args: BTreeMap<String, Value>,
+                #[serde(default)]
+                optional: bool,
+            },
+        }
+
+        Ok(match Spec::deserialize(deserializer)? {
+            Spec::Name(task) => Self::new(&task),
+            Spec::Task { task, args, optional } => Self { task, args, optional },
+        })
+    }
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct Task {
     #[serde(skip_deserializing)]
     pub name: String,
@@ -19,17 +146,79 @@ pub struct Task {
     #[serde(default)]
     pub help: Option<String>,
 
-    #[serde(default)]
-    pub after: Vec<String>,
+    /// Tasks that run first if they run at all; they are not pulled into the run
+    #[serde(default, alias = "after")]
+    pub order_after: Vec<Dep>,
 
-    #[serde(default)]
-    pub before: Vec<String>,
+    /// Tasks that are pulled into the run and have to succeed first
+    #[serde(default, alias = "before")]
+    pub requires: Vec<Dep>,
 
     #[serde(default, deserialize_with = "deserialize_param_map")]
     pub params: Params,
 
     #[serde(default)]
     pub action: String,
+
+    #[serde(default)]
+    pub cmd: Vec<String>,
+
+    #[serde(default = "default_show")]
+    pub show: bool,
+
+    #[serde(default)]
+    pub inputs: Vec<String>,
+
+    #[serde(default, alias = "outs")]
+    pub outputs: Vec<String>,
+
+    #[serde(default = "default_cache")]
+    pub cache: bool,
+
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub timeout: Option<Duration>,
+
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub retry_delay: Option<Duration>,
+
+    #[serde(default, alias = "interpreter")]
+    pub shell: Option<String>,
+
+    /// Whether all the arguments after the task's own params are passed to it, rather than only
+    /// those after `--`.
+    #[serde(default)]
+    pub passthrough: bool,
+
+    /// The working directory of the task, relative to the Ottofile's directory.
+    #[serde(default)]
+    pub dir: Option<String>,
+}
+
+impl Default for Task {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            help: None,
+            order_after: vec![],
+            requires: vec![],
+            params: Params::new(),
+            action: String::new(),
+            cmd: vec![],
+            show: default_show(),
+            inputs: vec![],
+            outputs: vec![],
+            cache: default_cache(),
+            timeout: None,
+            retries: 0,
+            retry_delay: None,
+            shell: None,
+            passthrough: false,
+            dir: None,
+        }
+    }
 }
 
 impl Task {
@@ -37,18 +226,19 @@ impl Task {
     pub fn new(
         name: String,
         help: Option<String>,
-        after: Vec<String>,
-        before: Vec<String>,
+        order_after: Vec<Dep>,
+        requires: Vec<Dep>,
         params: Params,
         action: String,
     ) -> Self {
         Self {
             name,
             help,
-            after,
-            before,
+            order_after,
+            requires,
             params,
             action,
+            ..Self::default()
         }
     }
 }
@@ -66,6 +256,40 @@ fn test_namify() {
     assert_eq!(namify("--name"), "name".to_string());
 }
 
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+    assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+    assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+    assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+    assert!(parse_duration("-1s").is_err());
+    assert!(parse_duration("soon").is_err());
+
+    let task: Task = serde_yaml::from_str("timeout: 30s\nretries: 2\nretry_delay: 0.5\n").unwrap();
+    assert_eq!(task.timeout, Some(Duration::from_secs(30)));
+    assert_eq!(task.retries, 2);
+    assert_eq!(task.retry_delay, Some(Duration::from_millis(500)));
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(Duration::from_secs(5)), "5s");
+    assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+    assert_eq!(format_duration(Duration::from_secs(600)), "10m");
+    assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+    assert_eq!(format_duration(Duration::ZERO), "0s");
+    for duration in [
+        Duration::from_secs(5),
+        Duration::from_millis(250),
+        Duration::from_secs(5400),
+        Duration::from_micros(1500),
+        Duration::ZERO,
+    ] {
+        assert_eq!(parse_duration(&format_duration(duration)).unwrap(), duration);
+    }
+}
+
 pub fn deserialize_task_map<'de, D>(deserializer: D) -> Result<Tasks, D::Error>
 where
     D: Deserializer<'de>,
@@ -92,4 +316,19 @@ where
         }
     }
     deserializer.deserialize_map(TaskMap)
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_deps() {
+    let task: Task = serde_yaml::from_str("before:\n- lint\n- task: build\n  args: {profile: release, jobs: 4, features: [a, b]}\n").unwrap();
+    assert_eq!(task.requires[0], Dep::new("lint"));
+    assert_eq!(task.requires[1].task, "build");
+    assert_eq!(task.requires[1].args["profile"], Value::Item("release".to_string()));
+    assert_eq!(task.requires[1].args["jobs"], Value::Item("4".to_string()));
+    assert_eq!(task.requires[1].args["features"], Value::List(vec!["a".to_string(), "b".to_string()]));
+
+    let task: Task = serde_yaml::from_str("requires: [build]\norder_after:\n- task: lint\n  optional: true\n").unwrap();
+    assert_eq!(task.requires, vec![Dep::new("build")]);
+    assert!(task.order_after[0].optional);
+    assert!(serde_yaml::from_str::<Task>("before: [a]\nrequires: [b]\n").is_err());
+}
@@ -28,6 +28,8 @@ pub enum OttoParseError {
     ConfigError(#[from] crate::cfg::error::ConfigError),
     #[error("Clap parse error")]
     ClapError(#[from] clap::Error),
+    #[error("No tasks defined; is there an ottofile?")]
+    NoTasks,
     #[error("unknown error")]
     Unknown,
 }
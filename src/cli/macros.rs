@@ -144,10 +144,6 @@ macro_rules! str_to_usize {
 
 macro_rules! vec_of_strings {
     ($($x:expr),* $(,)?) => {{
-        let mut temp_vec = Vec::new();
-        $(
-            temp_vec.push(String::from($x));
-        )*
-        temp_vec
+        vec![$(String::from($x)),*]
     }}
 }
@@ -1,22 +1,39 @@
 //#![allow(unused_imports, unused_variables, unused_attributes, unused_mut, dead_code)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{value_parser, Arg, Command};
+use clap::builder::PossibleValuesParser;
+use clap::{value_parser, Arg, ArgAction, Command};
 use daggy::{Dag, NodeIndex};
 use expanduser::expanduser;
 use eyre::{eyre, Result};
 use hex;
 use sha2::{Digest, Sha256};
 
-use crate::cfg::config::{Config, Otto, Param, Task, Tasks, Value};
-
-pub type DAG<T> = Dag<T, (), u32>;
+use crate::cfg::config::{Config, Dep, Otto, Param, Task, Tasks, Value};
+use crate::cfg::param::{Nargs, ParamType};
+use crate::cfg::task::{format_duration, parse_duration, DEFAULT_RETRY_DELAY};
+use crate::cli::error::OttoParseError;
+use crate::cmd::argv;
+use crate::cmd::env::is_valid_name;
+use crate::cmd::script::{shebang, DEFAULT_SHELL};
+
+pub type DAG<T> = Dag<T, Edge, u32>;
+
+/// How a task depends on the task an edge of the DAG comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// It requires it: the task is pulled into the run, and has to succeed first
+    Requires,
+    /// It is only ordered after it, if both run
+    OrderAfter,
+}
 
 const OTTOFILES: &[&str] = &[
     "otto.yml",
@@ -27,7 +44,10 @@ const OTTOFILES: &[&str] = &[
     "OTTOFILE",
 ];
 
-const DEFAULT_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// The id of the clap argument that collects the arguments passed through to a task.
+const PASSTHROUGH_ARGS: &str = "PASSTHROUGH ARGS";
+
+pub const DEFAULT_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
 fn calculate_hash(action: &String) -> String {
     let mut hasher = Sha256::new();
@@ -85,8 +105,8 @@ fn path_relative_from(path: &Path, base: &Path) -> Option<PathBuf> {
                 }
                 (None, _) => comps.push(Component::ParentDir),
                 (Some(a), Some(b)) if comps.is_empty() && a == b => (),
-                (Some(a), Some(b)) if b == Component::CurDir => comps.push(a),
-                (Some(_), Some(b)) if b == Component::ParentDir => return None,
+                (Some(a), Some(Component::CurDir)) => comps.push(a),
+                (Some(_), Some(Component::ParentDir)) => return None,
                 (Some(a), Some(_)) => {
                     comps.push(Component::ParentDir);
                     for _ in itb {
@@ -113,12 +133,33 @@ fn path_relative_from(path: &Path, base: &Path) -> Option<PathBuf> {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TaskSpec {
+    /// The name of the DAG node, which is the task's own unless the task has several nodes.
     pub name: String,
+    /// The name of the task in the Ottofile that the node runs.
+    pub task: String,
     pub deps: Vec<String>,
     pub envs: HashMap<String, String>,
     pub values: HashMap<String, Value>,
     pub action: String,
     pub hash: String,
+    pub show: bool,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub cache: bool,
+    pub fingerprint: String,
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    /// The interpreter the action is passed to, or `None` to execute it through its shebang.
+    pub shell: Option<String>,
+    /// Arguments to execute directly instead of the action, before interpolation.
+    pub cmd: Vec<String>,
+    /// The directory the task runs in and resolves its inputs and outputs against: its `dir`,
+    /// relative to the Ottofile's directory, or by default the Ottofile's directory itself. The
+    /// parser always sets it; only a spec built by hand without one runs in the invocation directory.
+    pub dir: Option<PathBuf>,
+    /// Arguments from the command line that are passed on to the action or `cmd` as they are.
+    pub args: Vec<String>,
 }
 
 impl TaskSpec {
@@ -131,23 +172,111 @@ impl TaskSpec {
         action: String,
     ) -> Self {
         let hash = calculate_hash(&action);
+        let shell = shebang(&action).is_none().then(|| DEFAULT_SHELL.to_string());
         Self {
+            task: name.clone(),
             name,
             deps,
             envs,
             values,
             action,
             hash,
+            show: true,
+            inputs: vec![],
+            outputs: vec![],
+            cache: true,
+            fingerprint: String::new(),
+            timeout: None,
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            shell,
+            cmd: vec![],
+            dir: None,
+            args: vec![],
         }
     }
+    /// The arguments a `cmd` task executes, with its parameter values interpolated and the
+    /// arguments passed through to it appended.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the `cmd` refers to an unknown parameter.
+    pub fn argv(&self) -> Result<Vec<String>> {
+        let mut argv = argv::interpolate(&self.cmd, &self.values).map_err(|e| eyre!("Task {}: {}", self.name, e))?;
+        argv.extend(self.args.iter().cloned());
+        Ok(argv)
+    }
+
     #[must_use]
     pub fn from_task(task: &Task) -> Self {
         let name = task.name.clone();
-        let deps = task.before.clone();
+        let deps = task.requires.iter().map(|dep| dep.task.clone()).collect();
         let envs = HashMap::new();
         let values = HashMap::new();
         let action = task.action.clone();
-        Self::new(name, deps, envs, values, action)
+        Self {
+            show: task.show,
+            inputs: task.inputs.clone(),
+            outputs: task.outputs.clone(),
+            cache: task.cache,
+            timeout: task.timeout,
+            retries: task.retries,
+            retry_delay: task.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY),
+            cmd: task.cmd.clone(),
+            ..Self::new(name, deps, envs, values, action)
+        }
+    }
+
+    /// The name of the task's files in the run and store directories, which is its name made safe
+    /// to use as one: see [`file_stem`].
+    #[must_use]
+    pub fn file_stem(&self) -> String {
+        file_stem(&self.name)
+    }
+}
+
+/// Turns the name of a DAG node, which may hold any param value, into a file name of its own:
+/// characters other than letters, digits and `_-=,+#[]` are percent-encoded, as is a leading dot,
+/// and a name too long for a file is cut short and told apart by a hash of the whole.
+#[must_use]
+pub fn file_stem(name: &str) -> String {
+    const MAX_LEN: usize = 200;
+    let mut stem = String::new();
+    for (i, byte) in name.bytes().enumerate() {
+        let safe = byte.is_ascii_alphanumeric() || b"_-=,+#[]".contains(&byte) || (byte == b'.' && i > 0);
+        if safe {
+            stem.push(char::from(byte));
+        } else {
+            stem.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    if stem.len() > MAX_LEN {
+        stem.truncate(MAX_LEN - 17);
+        stem = format!("{stem}-{}", &calculate_hash(&name.to_string())[..16]);
+    }
+    stem
+}
+
+/// Commands built into otto itself rather than defined in the Ottofile. They are recognized only
+/// before the first task name, and a task with the same name takes precedence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Builtin {
+    /// `otto hash [TASK...]` prints task fingerprints instead of running the tasks.
+    Hash,
+    /// `otto cache <stats|prune>` manages the local output cache; it takes all remaining arguments.
+    Cache(Vec<String>),
+    /// `otto cache-server` serves a directory as a remote cache; it takes all remaining arguments.
+    CacheServer(Vec<String>),
+}
+
+impl Builtin {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hash" => Some(Self::Hash),
+            "cache" => Some(Self::Cache(vec![])),
+            "cache-server" => Some(Self::CacheServer(vec![])),
+            _ => None,
+        }
     }
 }
 
@@ -158,28 +287,225 @@ pub struct Parser {
     user: String,
     config: Config,
     hash: String,
+    ottofile: Option<PathBuf>,
     args: Vec<String>,
     pargs: Vec<Vec<String>>,
+    builtin: Option<Builtin>,
 }
 
-fn indices(args: &[String], task_names: &[&str]) -> Vec<usize> {
-    let mut indices = vec![0];
-    for (i, arg) in args.iter().enumerate() {
+/// Removes the first builtin command name that appears before any task name from `args`.
+fn take_builtin(args: &mut Vec<String>, task_names: &[&str]) -> Option<Builtin> {
+    for (i, arg) in args.iter().enumerate().skip(1) {
         if task_names.contains(&arg.as_str()) {
-            indices.push(i);
+            return None;
+        }
+        if let Some(mut builtin) = Builtin::from_name(arg) {
+            if let Builtin::Cache(rest) | Builtin::CacheServer(rest) = &mut builtin {
+                *rest = args.split_off(i + 1);
+            }
+            args.remove(i);
+            return Some(builtin);
         }
     }
-    indices
+    None
+}
+
+/// The tokens that end the arguments of one task on the command line, so that the next one can
+/// be named where it would otherwise be taken as an argument.
+const SEPARATORS: &[&str] = &["+", "::"];
+
+/// Whether the next argument of a command has to be a value, could be one, or cannot be one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Slot {
+    Required,
+    Optional,
+    Closed,
+}
+
+/// Follows the arguments of one command, otto's or a task's, as its clap definition takes them,
+/// to tell the values of its arguments from the names of the tasks after it.
+struct Cursor {
+    command: Command,
+    /// The minimum and maximum number of values of each positional, and whether it takes all the
+    /// arguments after it
+    positionals: Vec<(usize, usize, bool)>,
+    positional: usize,
+    count: usize,
+    /// Whether every argument from here on belongs to the command, after `--` or once a trailing
+    /// positional has started
+    trailing: bool,
 }
 
-fn partitions(args: &Vec<String>, task_names: &[&str]) -> Vec<Vec<String>> {
-    let mut partitions = vec![];
-    let mut end = args.len();
-    for index in indices(args, task_names).iter().rev() {
-        partitions.insert(0, args[*index..end].to_vec());
-        end = *index;
+impl Cursor {
+    fn new(command: Command) -> Self {
+        let mut positionals: Vec<&Arg> = command.get_positionals().filter(|arg| !arg.is_last_set()).collect();
+        positionals.sort_by_key(|arg| arg.get_index());
+        let positionals = positionals
+            .iter()
+            .map(|arg| {
+                let range = arg.get_num_args().unwrap_or_default();
+                let min = if arg.is_required_set() { range.min_values() } else { 0 };
+                (min, range.max_values(), arg.is_trailing_var_arg_set())
+            })
+            .collect();
+        Self {
+            command,
+            positionals,
+            positional: 0,
+            count: 0,
+            trailing: false,
+        }
+    }
+
+    /// Whether the next positional argument has to be a value, could be one, or cannot be one.
+    fn slot(&mut self) -> Slot {
+        while let Some((_, max, _)) = self.positionals.get(self.positional) {
+            if self.count < *max {
+                break;
+            }
+            self.positional += 1;
+            self.count = 0;
+        }
+        match self.positionals.get(self.positional) {
+            Some((min, _, _)) if self.count < *min => Slot::Required,
+            Some(_) => Slot::Optional,
+            None => Slot::Closed,
+        }
+    }
+
+    /// Takes the next argument as a value of the current positional.
+    fn take(&mut self) {
+        if self.slot() != Slot::Closed {
+            if self.positionals[self.positional].2 {
+                self.trailing = true;
+            }
+            self.count += 1;
+        }
+    }
+
+    /// The option `token` names, and the least and most values that have to follow it, or `None`
+    /// if it is not an option of the command.
+    fn option(&self, token: &str) -> Option<(String, usize, usize)> {
+        let (arg, inline) = if let Some(long) = token.strip_prefix("--") {
+            let (long, value) = long.split_once('=').map_or((long, None), |(long, value)| (long, Some(value)));
+            let arg = self.command.get_arguments().find(|arg| arg.get_long() == Some(long))?;
+            (arg, value.is_some())
+        } else {
+            // A cluster of short flags, where the last one may take a value, or a short option
+            // with its value attached
+            let shorts = token.strip_prefix('-')?;
+            let mut arg = None;
+            for (i, short) in shorts.char_indices() {
+                let found = self.command.get_arguments().find(|arg| arg.get_short() == Some(short))?;
+                let takes_values = found.get_num_args().is_some_and(|range| range.max_values() > 0);
+                arg = Some((found, takes_values && i + short.len_utf8() < shorts.len()));
+                if takes_values {
+                    break;
+                }
+            }
+            arg?
+        };
+        let range = arg.get_num_args().unwrap_or_default();
+        let (min, max) = if inline {
+            (range.min_values().saturating_sub(1), range.max_values().saturating_sub(1))
+        } else {
+            (range.min_values(), range.max_values())
+        };
+        Some((token.split('=').next().unwrap_or(token).to_string(), min, max))
+    }
+}
+
+/// Whether `token` is a negative number, which is a value rather than an option, as clap takes
+/// it for params.
+fn is_negative_number(token: &str) -> bool {
+    token.strip_prefix('-').is_some_and(|number| {
+        number.starts_with(|c: char| c.is_ascii_digit() || c == '.') && number.parse::<f64>().is_ok()
+    })
+}
+
+/// Splits `args` into the arguments of otto itself and those of every task named on the command
+/// line, each starting with the task name.
+///
+/// A task name is only taken as one where a task can start: once the arguments of the command
+/// before it, otto's or a task's, have been taken as its clap definition says. So `otto deploy
+/// --env build` passes `build` to `--env` rather than running it. Everything after `--` belongs
+/// to the task before it, and `+` or `::` end its arguments explicitly, so `otto test -- -q +
+/// lint` runs two tasks. A task name that could be either an argument or a task is an error.
+///
+/// Only the commands of the tasks named on the command line are asked for, with `command`.
+///
+/// # Errors
+///
+/// This function will return an error if the command line is ambiguous, if a separator is not
+/// followed by a task name, or if `command` fails.
+fn partitions<F>(args: &[String], otto: Command, names: &[&str], command: F) -> Result<Vec<Vec<String>>>
+where
+    F: Fn(&str) -> Result<Command>,
+{
+    let find = |name: &str| names.iter().copied().find(|task| *task == name);
+    let Some((prog, mut rest)) = args.split_first() else { return Ok(vec![]) };
+    let mut partitions = vec![vec![prog.clone()]];
+    let mut cursor = Cursor::new(otto);
+    let ambiguous = |token: &str, cursor: &Cursor, what: &str| {
+        eyre!(
+            "ambiguous arguments: {} could be {} of {} or the task {}; put + or :: before it to run it as a task",
+            token,
+            what,
+            cursor.command.get_name(),
+            token
+        )
+    };
+
+    while let Some((token, tail)) = rest.split_first() {
+        rest = tail;
+        let partition = partitions.last_mut().expect("there is always a partition");
+        if SEPARATORS.contains(&token.as_str()) {
+            let (name, tail) = rest
+                .split_first()
+                .ok_or_else(|| eyre!("expected a task name after {}", token))?;
+            let task = find(name).ok_or_else(|| eyre!("expected a task name after {}, found {}", token, name))?;
+            rest = tail;
+            partitions.push(vec![name.clone()]);
+            cursor = Cursor::new(command(task)?);
+        } else if cursor.trailing {
+            partition.push(token.clone());
+        } else if token == "--" {
+            partition.push(token.clone());
+            cursor.trailing = true;
+        } else if let Some((option, min, max)) = token.starts_with('-').then(|| cursor.option(token)).flatten() {
+            partition.push(token.clone());
+            // Values that have to follow an option are its values, even if they name a task
+            for count in 0..max {
+                let Some((value, tail)) = rest.split_first() else { break };
+                let flag = value.starts_with('-') && !is_negative_number(value);
+                if flag || SEPARATORS.contains(&value.as_str()) {
+                    break;
+                }
+                if count >= min && find(value).is_some() {
+                    return Err(ambiguous(value, &cursor, &format!("a value for {option}")));
+                }
+                partition.push(value.clone());
+                rest = tail;
+            }
+        } else if let Some(task) = find(token) {
+            match cursor.slot() {
+                Slot::Required => {
+                    partition.push(token.clone());
+                    cursor.take();
+                }
+                Slot::Optional => return Err(ambiguous(token, &cursor, "an argument")),
+                Slot::Closed => {
+                    partitions.push(vec![token.clone()]);
+                    cursor = Cursor::new(command(task)?);
+                }
+            }
+        } else {
+            // Anything else is the command's to make sense of, or to reject
+            partition.push(token.clone());
+            cursor.take();
+        }
     }
-    partitions
+    Ok(partitions)
 }
 
 impl Parser {
@@ -190,21 +516,46 @@ impl Parser {
             .and_then(OsStr::to_str)
             .map_or_else(|| "otto".to_string(), std::string::ToString::to_string);
         let cwd = env::current_dir()?;
-        let user = env::var("USER")?;
-        let (config, hash) = Self::load_config(&mut args)?;
+        let user = env::var("USER").unwrap_or_default();
+        let (config, hash, ottofile) = Self::load_config(&mut args)?;
         let task_names: Vec<&str> = config.tasks.keys().map(std::string::String::as_str).collect();
-        let pargs = partitions(&args, &task_names);
+        let builtin = take_builtin(&mut args, &task_names);
+        let pargs = Self::partition(&args, &config)?;
         Ok(Self {
             prog,
             cwd,
             user,
             config,
             hash,
+            ottofile,
             args,
             pargs,
+            builtin,
         })
     }
 
+    /// Splits `args` with [`partitions`], as otto and the tasks of `config` take their arguments.
+    /// A task is only checked and turned into a command once it is named, so that a broken task
+    /// only gets in the way of running it.
+    fn partition(args: &[String], config: &Config) -> Result<Vec<Vec<String>>> {
+        let mut otto = Self::otto_to_command(&config.otto, &HashMap::new());
+        otto.build();
+        let names: Vec<&str> = config.tasks.keys().map(String::as_str).collect();
+        partitions(args, otto, &names, |name| {
+            let task = &config.tasks[name];
+            Self::check_params(task, &config.otto.env_prefix)?;
+            let mut command = Self::task_to_command(task);
+            command.build();
+            Ok(command)
+        })
+    }
+
+    /// The builtin command given on the command line, if any.
+    #[must_use]
+    pub const fn builtin(&self) -> Option<&Builtin> {
+        self.builtin.as_ref()
+    }
+
     fn find_ottofile(path: &Path) -> Result<Option<PathBuf>> {
         let cwd = env::current_dir()?;
         for ottofile in OTTOFILES {
@@ -231,7 +582,9 @@ impl Parser {
         Ok(Some(path))
     }
 
-    fn load_config(args: &mut Vec<String>) -> Result<(Config, String)> {
+    /// Loads the Ottofile named by `--ottofile`, `$OTTOFILE`, or found in the current directory or
+    /// one of its parents, and returns it with its hash and absolute path.
+    fn load_config(args: &mut Vec<String>) -> Result<(Config, String, Option<PathBuf>)> {
         let index = args.iter().position(|x| x == "--ottofile");
         let value = index.map_or_else(
             || env::var("OTTOFILE").unwrap_or_else(|_| "./".to_owned()),
@@ -243,12 +596,12 @@ impl Parser {
             },
         );
         if let Some(ottofile) = Self::divine_ottofile(value)? {
-            let content = fs::read_to_string(ottofile)?;
+            let content = fs::read_to_string(&ottofile)?;
             let hash = calculate_hash(&content);
             let config: Config = serde_yaml::from_str(&content)?;
-            Ok((config, hash))
+            Ok((config, hash, Some(fs::canonicalize(ottofile)?)))
         } else {
-            Ok((Config::default(), DEFAULT_HASH.to_owned()))
+            Ok((Config::default(), DEFAULT_HASH.to_owned(), None))
         }
     }
 
@@ -279,7 +632,7 @@ impl Parser {
                     .short('j')
                     .long("jobs")
                     .value_name("JOBS")
-                    .default_value(&otto.jobs.to_string())
+                    .default_value(otto.jobs.to_string())
                     .value_parser(value_parser!(usize))
                     .help("number of jobs to run in parallel"),
             )
@@ -308,6 +661,51 @@ impl Parser {
                     .value_name("LEVEL")
                     .default_value("1")
                     .help("verbosity level"),
+            )
+            .arg(
+                Arg::new("output")
+                    .long("output")
+                    .value_name("MODE")
+                    .default_value(otto.output.to_string())
+                    .value_parser(["interleaved", "grouped", "quiet"])
+                    .help("how task output is shown"),
+            )
+            .arg(
+                Arg::new("keep-going")
+                    .short('k')
+                    .long("keep-going")
+                    .action(ArgAction::SetTrue)
+                    .overrides_with("fail-fast")
+                    .help("keep running every task not downstream of a failed task"),
+            )
+            .arg(
+                Arg::new("fail-fast")
+                    .long("fail-fast")
+                    .action(ArgAction::SetTrue)
+                    .overrides_with("keep-going")
+                    .help("cancel the run on the first failed task [default]"),
+            )
+            .arg(
+                Arg::new("dry-run")
+                    .short('n')
+                    .long("dry-run")
+                    .action(ArgAction::SetTrue)
+                    .help("print what the selected tasks would run instead of running them"),
+            )
+            .arg(
+                Arg::new("grace-period")
+                    .long("grace-period")
+                    .value_name("DURATION")
+                    .default_value(format_duration(otto.grace_period))
+                    .value_parser(|s: &str| parse_duration(s).map_err(|e| e.to_string()))
+                    .help("how long tasks get to exit after Ctrl-C before they are killed"),
+            )
+            .arg(
+                Arg::new("invocation-dir")
+                    .long("invocation-dir")
+                    .value_name("PATH")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("directory to export as OTTO_INVOCATION_DIR [default: the current directory]"),
             );
         for task in tasks.values() {
             command = command.subcommand(Self::task_to_command(task));
@@ -320,10 +718,24 @@ impl Parser {
         if let Some(task_help) = &task.help {
             command = command.about(task_help);
         }
-        for param in task.params.values() {
+        // Positionals are taken in the order they are declared in
+        let mut params: Vec<&Param> = task.params.values().collect();
+        params.sort_by_key(|param| param.order);
+        for param in params {
             command = command.arg(Self::param_to_arg(param));
         }
-        command
+        // Whatever is left is passed through to the action: everything after `--`, or with
+        // passthrough everything from the first argument that is not a param on
+        let args = Arg::new(PASSTHROUGH_ARGS)
+            .value_name("ARGS")
+            .num_args(0..)
+            .allow_hyphen_values(true)
+            .help("Arguments passed through to the task");
+        if task.passthrough {
+            command.arg(args.trailing_var_arg(true))
+        } else {
+            command.arg(args.last(true))
+        }
     }
 
     fn param_to_arg(param: &Param) -> Arg {
@@ -334,18 +746,179 @@ impl Parser {
         if let Some(long) = &param.long {
             arg = arg.long(long);
         }
-        // if param.param_type == ParamType::OPT {
-        //     arg = arg.takes_value(true);
-        // }
+        arg = match param.nargs {
+            Nargs::Zero => arg.action(ArgAction::SetTrue),
+            Nargs::One => arg.num_args(1),
+            Nargs::OneOrZero => arg.num_args(0..=1),
+            Nargs::OneOrMore => arg.num_args(1..),
+            Nargs::ZeroOrMore => arg.num_args(0..),
+            Nargs::Range(min, max) => arg.num_args(min..=max),
+        };
+        // Like argparse, an optional value that is left out is the constant
+        if let (Nargs::OneOrZero, Value::Item(constant)) = (&param.nargs, &param.constant) {
+            arg = arg.default_missing_value(constant);
+        }
+        if param.nargs != Nargs::Zero {
+            arg = arg.allow_negative_numbers(true);
+            if !param.choices.is_empty() {
+                arg = arg.value_parser(PossibleValuesParser::new(&param.choices));
+            }
+            if let Some(metavar) = &param.metavar {
+                arg = arg.value_name(metavar);
+            }
+        }
+        // Like argparse, a positional has to be given unless it can be left empty or has a default
+        if param.param_type == ParamType::POS {
+            let optional = matches!(param.nargs, Nargs::OneOrZero | Nargs::ZeroOrMore | Nargs::Range(0, _));
+            arg = arg.required(!optional && param.default.is_none());
+        }
         if let Some(help) = &param.help {
             arg = arg.help(help);
         }
-        if let Some(default) = &param.default {
+        if let Some(default) = param.default.as_ref().filter(|_| param.nargs != Nargs::Zero) {
             arg = arg.default_value(default);
         }
         arg
     }
 
+    /// Checks that the params of `task` make a valid clap command, that their defaults are among
+    /// their choices, and that every param has an environment variable of its own, named `prefix`
+    /// followed by its `dest`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if two params share a flag, a required positional
+    /// follows an optional one, a default is not one of the choices, a variable name is not valid,
+    /// or two params have the same one.
+    fn check_params(task: &Task, prefix: &str) -> Result<()> {
+        let mut params: Vec<&Param> = task.params.values().collect();
+        params.sort_by_key(|param| param.order);
+        let mut names: HashMap<String, &str> = HashMap::new();
+        let mut flags: HashMap<String, &str> =
+            HashMap::from([("-h".to_string(), "help"), ("--help".to_string(), "help")]);
+        let mut optional: Option<&str> = None;
+        for param in params {
+            let short = param.short.map(|short| format!("-{short}"));
+            let long = param.long.as_ref().map(|long| format!("--{long}"));
+            for flag in short.into_iter().chain(long) {
+                if let Some(other) = flags.insert(flag.clone(), &param.name) {
+                    return Err(eyre!(
+                        "Task {} params {} and {} both use {}",
+                        task.name,
+                        other,
+                        param.name,
+                        flag
+                    ));
+                }
+            }
+            if param.param_type == ParamType::POS {
+                let arg = Self::param_to_arg(param);
+                match optional {
+                    Some(other) if arg.is_required_set() => {
+                        return Err(eyre!(
+                            "Task {} positional {} is required but follows the optional positional {}",
+                            task.name,
+                            param.name,
+                            other
+                        ));
+                    }
+                    None if !arg.is_required_set() => optional = Some(&param.name),
+                    _ => {}
+                }
+            }
+            // With passthrough, clap cannot tell where a positional list ends and the arguments
+            // passed through begin
+            if task.passthrough && param.param_type == ParamType::POS && param.nargs.is_list() {
+                return Err(eyre!(
+                    "Task {} param {} takes several values, which a passthrough task cannot tell apart \
+                     from the arguments it passes through; make it take one value or drop passthrough",
+                    task.name,
+                    param.name
+                ));
+            }
+            if let Some(default) = param.default.as_ref().filter(|_| !param.choices.is_empty()) {
+                if !param.choices.contains(default) {
+                    return Err(eyre!(
+                        "Task {} param {} has a default of {} which is not one of its choices: {}",
+                        task.name,
+                        param.name,
+                        default,
+                        param.choices.join(", ")
+                    ));
+                }
+            }
+            let name = format!("{prefix}{}", param.dest());
+            if !is_valid_name(&name) {
+                return Err(eyre!(
+                    "Task {} param {} would be exported as {}, which is not a valid environment variable name; \
+                     set a dest for it",
+                    task.name,
+                    param.name,
+                    name
+                ));
+            }
+            if let Some(other) = names.insert(name.clone(), &param.name) {
+                return Err(eyre!(
+                    "Task {} params {} and {} would both be exported as {}; set a dest for one of them",
+                    task.name,
+                    other,
+                    param.name,
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of `param` when it is not given on the command line: its default, as a list of
+    /// one if it takes several values, or nothing.
+    fn default_value(param: &Param) -> Value {
+        match &param.default {
+            Some(default) if param.nargs.is_list() => Value::List(vec![default.clone()]),
+            Some(default) => Value::Item(default.clone()),
+            None => Value::Empty,
+        }
+    }
+
+    /// The value of the flag `param` when it is given: its constant, or else the opposite of its
+    /// default, which is `true` unless the default is `true`.
+    fn switch_value(param: &Param) -> Value {
+        match &param.constant {
+            Value::Empty if param.default.as_deref() == Some("true") => Value::Item("false".to_string()),
+            Value::Empty => Value::Item("true".to_string()),
+            constant => constant.clone(),
+        }
+    }
+
+    /// The values `args` give the parameters of `task`, keyed by parameter name, and the arguments
+    /// that are passed through to it. Parameters that
+    /// take several values are collected into lists. Flags, which take none, are set to their
+    /// [`switch_value`](Self::switch_value) when they are given.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `args` do not match the parameters, such as when a
+    /// parameter is given fewer or more values than its `nargs` allows.
+    fn task_values(task: &Task, args: &[String]) -> Result<(HashMap<String, Value>, Vec<String>), clap::Error> {
+        let matches = Self::task_to_command(task).try_get_matches_from(args)?;
+        let mut values = HashMap::new();
+        for param in task.params.values() {
+            let name = param.name.as_str();
+            let value = match &param.nargs {
+                Nargs::Zero => matches.get_flag(name).then(|| Self::switch_value(param)),
+                nargs if nargs.is_list() => matches
+                    .get_many::<String>(name)
+                    .map(|items| Value::List(items.cloned().collect())),
+                _ => matches.get_one::<String>(name).cloned().map(Value::Item),
+            };
+            if let Some(value) = value {
+                values.insert(param.dest(), value);
+            }
+        }
+        let passthrough = matches.get_many::<String>(PASSTHROUGH_ARGS).unwrap_or_default().cloned().collect();
+        Ok((values, passthrough))
+    }
+
     pub fn parse(&mut self) -> Result<(Otto, DAG<TaskSpec>, String)> {
         // Create clap commands for 'otto' and jobs
         let otto_command = Self::otto_to_command(&self.config.otto, &self.config.tasks);
@@ -353,11 +926,17 @@ impl Parser {
         // Parse 'otto' command and update Otto fields
         let mut otto = self.parse_otto_command(otto_command, &self.pargs[0])?;
 
-        // Process the jobs with their default values and command line parameters
-        let tasks = self.process_tasks()?;
+        // Collect the node of every task given on the command line
+        let configured_tasks = self.occurrences().into_iter().map(|(name, _)| name).collect::<Vec<String>>();
 
-        // Collect the first item in each parg, skipping the first one.
-        let configured_tasks = self.pargs.iter().skip(1).map(|p| p[0].clone()).collect::<Vec<String>>();
+        // Process the jobs with their default values and command line parameters; the cache
+        // builtins run no tasks, so none of them has to be valid
+        let requested: Vec<String> = match self.builtin {
+            Some(Builtin::Cache(_) | Builtin::CacheServer(_)) => vec![],
+            _ if configured_tasks.is_empty() => otto.tasks.clone(),
+            _ => self.pargs[1..].iter().map(|partition| partition[0].clone()).collect(),
+        };
+        let tasks = self.process_tasks(&self.runnable(&requested))?;
 
         // If tasks were passed as arguments, they replace the default tasks.
         // Otherwise, the default tasks remain.
@@ -371,70 +950,297 @@ impl Parser {
         Ok((otto, tasks, self.hash.clone()))
     }
 
-    fn process_tasks(&self) -> Result<DAG<TaskSpec>> {
-        // Initialize an empty Dag and an index map
-        let mut dag: DAG<TaskSpec> = DAG::new();
-        let mut indices: HashMap<String, NodeIndex<u32>> = HashMap::new();
+    /// The absolute path of the Ottofile, if one was found.
+    #[must_use]
+    pub fn ottofile(&self) -> Option<&Path> {
+        self.ottofile.as_deref()
+    }
 
-        // Iterate through the tasks loaded from the Ottofile
-        for task in self.config.tasks.values() {
-            // Create a new job based on the task
-            let mut spec = TaskSpec::from_task(task);
-
-            // Apply the default values for each task
-            for (name, param) in &task.params {
-                if let Some(default_value) = &param.default {
-                    let value = Value::Item(default_value.clone());
-                    spec.values.insert(name.clone(), value);
+    /// The directory of the Ottofile, which tasks run in unless they say otherwise; without an
+    /// Ottofile, the current directory.
+    #[must_use]
+    pub fn project_dir(&self) -> PathBuf {
+        self.ottofile
+            .as_deref()
+            .and_then(Path::parent)
+            .map_or_else(|| self.cwd.clone(), Path::to_path_buf)
+    }
+
+    /// Every task occurrence on the command line, with the name of its DAG node: the task name, or
+    /// `name#n` for the nth occurrence of a task that is given more than once.
+    fn occurrences(&self) -> Vec<(String, &[String])> {
+        let partitions = self.pargs.get(1..).unwrap_or_default();
+        let count = |name: &str| partitions.iter().filter(|partition| partition[0] == name).count();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        partitions
+            .iter()
+            .map(|partition| {
+                let name = partition[0].as_str();
+                let nth = seen.entry(name).or_default();
+                *nth += 1;
+                let instance = if count(name) > 1 { format!("{name}#{nth}") } else { name.to_string() };
+                (instance, partition.as_slice())
+            })
+            .collect()
+    }
+
+    /// The name of the DAG node of a dependency on `task` with `values`, such as
+    /// `build[profile=release]`. A value that is not a plain word is quoted as JSON, so that
+    /// different values never make the same name.
+    fn dep_name(task: &str, values: &BTreeMap<String, Value>) -> String {
+        let plain = |item: &str| {
+            !item.is_empty() && item.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:@+".contains(c))
+        };
+        fn json<T: serde::Serialize>(value: &T) -> String {
+            serde_json::to_string(value).unwrap_or_default()
+        }
+        let args: Vec<String> = values
+            .iter()
+            .map(|(key, value)| match value {
+                Value::Item(item) if plain(item) => format!("{key}={item}"),
+                Value::Item(_) | Value::List(_) => format!("{key}={}", json(value)),
+                Value::Dict(dict) => format!("{key}={}", json(&dict.iter().collect::<BTreeMap<_, _>>())),
+                Value::Empty => key.clone(),
+            })
+            .collect();
+        format!("{task}[{}]", args.join(","))
+    }
+
+    /// The command line that gives `task` the values the dependency `dep` runs it with, as if they
+    /// were typed after its name.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an arg is not a param of the task, or is a flag set to
+    /// something it cannot be.
+    fn dep_args(task: &Task, dep: &Dep) -> Result<Vec<String>> {
+        let mut options = vec![task.name.clone()];
+        let mut positionals = vec![];
+        for (key, value) in &dep.args {
+            let param = task
+                .params
+                .values()
+                .find(|param| param.name == *key || param.dest() == *key)
+                .ok_or_else(|| eyre!("task {} has no param {}", dep.task, key))?;
+            let flag = match (&param.long, param.short) {
+                (Some(long), _) => format!("--{long}"),
+                (None, short) => format!("-{}", short.unwrap_or_default()),
+            };
+            let items = match value {
+                Value::Item(item) => vec![item.clone()],
+                Value::List(items) => items.clone(),
+                Value::Dict(_) | Value::Empty => vec![],
+            };
+            match param.param_type {
+                ParamType::FLG if *value == Self::switch_value(param) => options.push(flag),
+                ParamType::FLG if *value == Self::default_value(param) => {}
+                ParamType::FLG => {
+                    return Err(eyre!("task {} flag {} cannot be {}", dep.task, param.name, items.join(" ")));
+                }
+                ParamType::POS => positionals.push((param.order, items)),
+                // A single value is attached, so it cannot be taken for an option
+                ParamType::OPT if items.len() == 1 => options.push(format!("{flag}={}", items[0])),
+                ParamType::OPT => {
+                    options.push(flag);
+                    options.extend(items);
                 }
             }
+        }
+        positionals.sort_by_key(|(order, _)| *order);
+        options.extend(positionals.into_iter().flat_map(|(_, items)| items));
+        Ok(options)
+    }
 
-            // Check if the task is mentioned in the command line and override the default values with the passed parameters
-            if let Some(task_args) = self.pargs[1..].iter().find(|partition| partition[0] == task.name) {
-                // Create a clap command for the given task
-                let task_command = Self::task_to_command(task);
+    /// The spec of the DAG node `name` for `task`, with the values and arguments to pass through
+    /// that its command line gives, or its defaults.
+    fn task_spec(
+        &self,
+        task: &Task,
+        name: String,
+        values: Option<(HashMap<String, Value>, Vec<String>)>,
+    ) -> Result<TaskSpec> {
+        // Create a new job based on the task
+        let mut spec = TaskSpec::from_task(task);
+        spec.name = name;
+
+        spec.dir = Some(match &task.dir {
+            Some(dir) => self.project_dir().join(expanduser(dir)?),
+            None => self.project_dir(),
+        });
+
+        // A shell set on the task wins over the action's shebang, which wins over the default shell
+        spec.shell = match &task.shell {
+            Some(shell) => Some(shell.clone()),
+            None if shebang(&task.action).is_some() => None,
+            None => Some(self.config.otto.shell.clone()),
+        };
 
-                // Parse args using the task command
-                let matches = task_command.get_matches_from(task_args);
+        // Apply the default values for each task; parameters without one are still known, but empty.
+        // Defaults are keyed like the values from the command line, rather than by the title of the
+        // param, so that a param reaches the action under the same name whether it was given or not;
+        // and every param is present, so that `${name}` in a `cmd` interpolates to nothing instead of
+        // being an unknown parameter when it has no default
+        for param in task.params.values() {
+            spec.values.insert(param.dest(), Self::default_value(param));
+        }
 
-                // Update the job fields with the parsed values
-                for param in task.params.values() {
-                    if let Some(value) = matches.get_one::<String>(param.name.as_str()) {
-                        spec.values.insert(param.name.clone(), Value::Item(value.to_string()));
-                    }
-                }
+        // Override the default values with the parameters passed on the command line
+        if let Some((values, args)) = values {
+            spec.values.extend(values);
+            spec.args = args;
+        }
+
+        if !task.cmd.is_empty() {
+            if !task.action.is_empty() {
+                return Err(eyre!("Task {} has both an action and a cmd", task.name));
             }
+            spec.argv()?;
+        }
+        Ok(spec)
+    }
 
-            // Add the processed job to the Dag and index map
-            let index = dag.add_node(spec.clone());
-            indices.insert(spec.name.clone(), index);
+    /// The names of the `requested` tasks and of the tasks they require, transitively, where "*"
+    /// requests every task.
+    fn runnable(&self, requested: &[String]) -> HashSet<&str> {
+        let mut runnable = HashSet::new();
+        let mut pending: Vec<&str> = if requested.iter().any(|task| task == "*") {
+            self.config.tasks.keys().map(String::as_str).collect()
+        } else {
+            requested.iter().map(String::as_str).collect()
+        };
+        while let Some(name) = pending.pop() {
+            if let Some(task) = self.config.tasks.get(name) {
+                if runnable.insert(task.name.as_str()) {
+                    pending.extend(task.requires.iter().map(|dep| dep.task.as_str()));
+                }
+            }
         }
+        runnable
+    }
 
-        // Iterate over the jobs a second time to handle 'after' dependencies
+    /// The DAG of every task in the Ottofile. A task given more than once on the command line has
+    /// a node for every time, each with its own values; they share the tasks they depend on, and
+    /// the tasks that depend on it depend on all of them. Only the `runnable` tasks have their
+    /// params checked, as in [`Self::partition`].
+    fn process_tasks(&self, runnable: &HashSet<&str>) -> Result<DAG<TaskSpec>> {
+        // Initialize an empty Dag and a map from every task to its nodes
+        let mut dag: DAG<TaskSpec> = DAG::new();
+        let mut indices: HashMap<String, Vec<NodeIndex<u32>>> = HashMap::new();
+        let occurrences = self.occurrences();
+
+        // Iterate through the tasks loaded from the Ottofile
         for task in self.config.tasks.values() {
-            for after_task_name in &task.after {
-                let node = indices.get(&task.name).expect("Job not found in indices");
-                let dep_node = indices.get(after_task_name).expect("Dependency not found in indices");
-                dag.add_edge(*dep_node, *node, ())?;
+            if runnable.contains(task.name.as_str()) {
+                Self::check_params(task, &self.config.otto.env_prefix)?;
+            }
+
+            let mut instances: Vec<(String, Option<&[String]>)> = occurrences
+                .iter()
+                .filter(|(_, partition)| partition[0] == task.name)
+                .map(|(name, partition)| (name.clone(), Some(*partition)))
+                .collect();
+            if instances.is_empty() {
+                instances.push((task.name.clone(), None));
+            }
+            for (name, args) in instances {
+                // Parse args using a clap command for the task; bad args print usage and exit, like clap does
+                let values = args.map(|args| Self::task_values(task, args).unwrap_or_else(|err| err.exit()));
+                let index = dag.add_node(self.task_spec(task, name, values)?);
+                indices.entry(task.name.clone()).or_default().push(index);
             }
         }
 
+        // Wire every node to the nodes of the tasks it runs after, both those it requires (which are
+        // pulled into the run) and those it is ordered after (which are not). A dependency with args
+        // gets a node of its own, shared by every task that depends on it with the same args, and
+        // wired in turn. Optional dependencies on tasks that do not exist are left out.
+        let mut parameterized: HashMap<String, NodeIndex<u32>> = HashMap::new();
+        let mut pending: Vec<(NodeIndex<u32>, &Task)> = self
+            .config
+            .tasks
+            .values()
+            .flat_map(|task| indices[&task.name].iter().map(move |index| (*index, task)))
+            .collect();
+        while let Some((node, task)) = pending.pop() {
+            let mut deps = vec![];
+            let requires = task.requires.iter().map(|dep| (dep, Edge::Requires));
+            for (dep, edge) in requires.chain(task.order_after.iter().map(|dep| (dep, Edge::OrderAfter))) {
+                let Some(dep_task) = self.config.tasks.get(&dep.task) else {
+                    if dep.optional {
+                        continue;
+                    }
+                    return Err(eyre!("task {} depends on unknown task {}", task.name, dep.task));
+                };
+                let dep_nodes = if dep.args.is_empty() {
+                    indices[&dep.task].clone()
+                } else {
+                    Self::check_params(dep_task, &self.config.otto.env_prefix)?;
+                    let values = Self::task_values(dep_task, &Self::dep_args(dep_task, dep)?).map_err(|err| {
+                        let message = err.to_string();
+                        let message = message.lines().next().unwrap_or_default().trim_start_matches("error: ");
+                        eyre!("task {} depends on {}: {}", task.name, Self::dep_name(&dep.task, &dep.args), message)
+                    })?;
+                    let mut spec = self.task_spec(dep_task, dep.task.clone(), Some(values))?;
+
+                    // Args are told apart by the values they resolve to, so that args that say the
+                    // same thing share a node, and args that leave every default as it is share the
+                    // task's own node
+                    let same = indices[&dep.task]
+                        .iter()
+                        .copied()
+                        .find(|index| dag[*index].values == spec.values && dag[*index].args.is_empty());
+                    let changed: BTreeMap<String, Value> = dep_task
+                        .params
+                        .values()
+                        .map(|param| (param.dest(), Self::default_value(param)))
+                        .filter(|(dest, default)| spec.values[dest] != *default)
+                        .map(|(dest, _)| (dest.clone(), spec.values[&dest].clone()))
+                        .collect();
+                    let name = Self::dep_name(&dep.task, &changed);
+                    let index = match same.or_else(|| parameterized.get(&name).copied()) {
+                        Some(index) => index,
+                        None => {
+                            spec.name = name.clone();
+                            let index = dag.add_node(spec);
+                            parameterized.insert(name, index);
+                            pending.push((index, dep_task));
+                            index
+                        }
+                    };
+                    vec![index]
+                };
+                // Two deps that resolve to the same node make one edge, a requiring one if either is
+                for dep_node in dep_nodes {
+                    if dag.find_edge(dep_node, node).is_some() {
+                        continue;
+                    }
+                    dag.add_edge(dep_node, node, edge)?;
+                    if edge == Edge::Requires {
+                        deps.push(dag[dep_node].name.clone());
+                    }
+                }
+            }
+            // Dependencies are pulled into the run by the names of their nodes
+            dag[node].deps = deps;
+        }
+
         // Return the completed Dag
         Ok(dag)
     }
 
     fn handle_no_input(&self) {
-        // Create a default otto command with no tasks
-        let otto_command = Self::otto_to_command(&self.config.otto, &HashMap::new());
-        otto_command.get_matches_from(["otto", "--help"]);
+        // Create a default otto command with no tasks and show its help
+        let mut otto_command = Self::otto_to_command(&self.config.otto, &HashMap::new());
+        otto_command.print_help().ok();
     }
 
     fn parse_otto_command(&self, otto_command: Command, args: &[String]) -> Result<Otto> {
         // Parse 'otto' command using args and update the Otto fields
 
         // if config.tasks is empty, then show default help for 'otto' command and exit
-        if self.config.tasks.is_empty() {
+        if self.config.tasks.is_empty() && self.builtin.is_none() {
             self.handle_no_input();
+            return Err(OttoParseError::NoTasks.into());
         }
 
         // Parse the arguments using clap's get_matches_from method
@@ -458,6 +1264,24 @@ impl Parser {
                 otto.jobs = *jobs;
             }
         }
+        if let Some(output) = matches.get_one::<String>("output") {
+            otto.output = output.parse().map_err(|e: String| eyre!(e))?;
+        }
+        if matches.get_flag("keep-going") {
+            otto.keep_going = true;
+        }
+        if matches.get_flag("fail-fast") {
+            otto.keep_going = false;
+        }
+        if matches.get_flag("dry-run") {
+            otto.dry_run = true;
+        }
+        if let Some(grace_period) = matches.get_one::<Duration>("grace-period") {
+            otto.grace_period = *grace_period;
+        }
+        if let Some(invocation_dir) = matches.get_one::<PathBuf>("invocation-dir") {
+            otto.invocation_dir = Some(invocation_dir.clone());
+        }
         if matches.contains_id("tasks") {
             if let Some(tasks) = matches.get_many::<String>("tasks") {
                 otto.tasks = tasks
@@ -481,23 +1305,149 @@ impl Parser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cfg::config::OutputMode;
+    use crate::testing::Ottofile;
     use std::collections::HashMap;
 
-    #[test]
-    fn test_indices() {
-        let args = vec_of_strings!["arg1", "task1", "arg2", "task2", "arg3",];
-        let task_names = &["task1", "task2"];
-        let expected = vec![0, 1, 3];
-        assert_eq!(indices(&args, task_names), expected);
+    /// Parses `args` as given to `task` on the command line into its values and the arguments passed
+    /// through to it.
+    fn parse_task(task: &Task, args: &[&str]) -> Result<(HashMap<String, Value>, Vec<String>), clap::Error> {
+        let args: Vec<String> =
+            std::iter::once(task.name.as_str()).chain(args.iter().copied()).map(String::from).collect();
+        Parser::task_values(task, &args)
+    }
+
+    /// The values `task` gets from `args`.
+    fn values(task: &Task, args: &[&str]) -> Result<HashMap<String, Value>, clap::Error> {
+        parse_task(task, args).map(|(values, _)| values)
+    }
+
+    fn test_config() -> Config {
+        let yaml = r#"
+tasks:
+  build: {}
+  deploy:
+    params:
+      -e|--env: {}
+      -r|--regions:
+        nargs: "*"
+  copy:
+    params:
+      src: {}
+      dst:
+        nargs: "?"
+  docker:
+    passthrough: true
+  shift:
+    params:
+      --offset: {}
+      -n|--numbers:
+        nargs: "+"
+      amount:
+        nargs: "?"
+"#;
+        serde_yaml::from_str(yaml).unwrap()
     }
 
     #[test]
     fn test_partitions() {
-        let args = vec_of_strings!["arg1", "task1", "arg2", "task2", "arg3",];
-        let task_names = vec!["task1", "task2"];
+        let config = test_config();
+        let partition = |args: &[&str]| {
+            let args: Vec<String> = std::iter::once("otto").chain(args.iter().copied()).map(String::from).collect();
+            Parser::partition(&args, &config)
+        };
+        let split = |args: &[&str]| partition(args).unwrap();
+
+        assert_eq!(split(&["-j", "2", "build"]), vec![vec!["otto", "-j", "2"], vec!["build"]]);
+        assert_eq!(split(&["build", "deploy", "-e", "prod"]), vec![vec!["otto"], vec!["build"], vec!["deploy", "-e", "prod"]]);
+
+        // Task names are values where a value has to be
+        assert_eq!(split(&["deploy", "--env", "build"]), vec![vec!["otto"], vec!["deploy", "--env", "build"]]);
+        assert_eq!(split(&["deploy", "-e=build", "build"]), vec![vec!["otto"], vec!["deploy", "-e=build"], vec!["build"]]);
+        assert_eq!(split(&["copy", "build", "+", "build"])[1], vec!["copy", "build"]);
+        assert_eq!(split(&["-o", "build", "build"])[0], vec!["otto", "-o", "build"]);
+
+        // Separators and --
+        assert_eq!(split(&["deploy", "-r", "us", "+", "build"]), vec![vec!["otto"], vec!["deploy", "-r", "us"], vec!["build"]]);
+        assert_eq!(split(&["copy", "a", "::", "build"]), vec![vec!["otto"], vec!["copy", "a"], vec!["build"]]);
+        assert_eq!(
+            split(&["build", "--", "deploy", "-e", "+", "deploy"]),
+            vec![vec!["otto"], vec!["build", "--", "deploy", "-e"], vec!["deploy"]]
+        );
+        assert_eq!(split(&["docker", "run", "build", "::", "build"])[1], vec!["docker", "run", "build"]);
+
+        // Negative numbers are values, not options
+        assert_eq!(split(&["shift", "--offset", "-5", "+", "build"])[1], vec!["shift", "--offset", "-5"]);
+        assert_eq!(
+            split(&["shift", "-n", "-1", "-2.5", "--offset", "3", "-7"]),
+            vec![vec!["otto"], vec!["shift", "-n", "-1", "-2.5", "--offset", "3", "-7"]]
+        );
+        assert_eq!(split(&["shift", "-n", "1", "--offset=-4"])[1], vec!["shift", "-n", "1", "--offset=-4"]);
+        let err = partition(&["shift", "-n", "1", "build"]).unwrap_err().to_string();
+        assert!(err.contains("build could be a value for -n of shift"), "{err}");
+        let values = values(&config.tasks["shift"], &["-3", "--offset", "-5", "-n", "-1", "-2"]).unwrap();
+        assert_eq!(values["offset"], Value::Item("-5".to_string()));
+        assert_eq!(values["numbers"], Value::List(vec_of_strings!["-1", "-2"]));
+        assert_eq!(values["amount"], Value::Item("-3".to_string()));
+
+        // A task name that could be a value or a task is an error, as is a separator without a task
+        let err = partition(&["deploy", "-r", "us", "build"]).unwrap_err().to_string();
+        assert!(err.contains("build could be a value for -r of deploy or the task build"), "{err}");
+        assert!(partition(&["copy", "a", "build"]).is_err());
+        assert!(partition(&["docker", "build"]).is_err());
+        assert!(partition(&["build", "+"]).is_err());
+        assert!(partition(&["build", "+", "nope"]).is_err());
+
+        // A broken task only gets in the way of running it
+        let yaml = "tasks:\n  build: {}\n  broken:\n    params:\n      -e|--env: {}\n      -e|--exec: {}\n";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let partition = |args: &[&str]| {
+            let args: Vec<String> = std::iter::once("otto").chain(args.iter().copied()).map(String::from).collect();
+            Parser::partition(&args, &config)
+        };
+        assert_eq!(partition(&["build"]).unwrap(), vec![vec!["otto"], vec!["build"]]);
+        let err = partition(&["build", "broken"]).unwrap_err().to_string();
+        assert!(err.contains("Task broken params env and exec both use -e"), "{err}");
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("build"), "build");
+        assert_eq!(file_stem("deploy#2"), "deploy#2");
+        assert_eq!(file_stem("build[dir=a/b]"), "build[dir=a%2Fb]");
+        assert_eq!(file_stem(".."), "%2E.");
+        assert_eq!(file_stem("a b%"), "a%20b%25");
+        let long = file_stem(&format!("build[dir={}]", "x".repeat(300)));
+        assert_eq!(long.len(), 200);
+        assert_ne!(long, file_stem(&format!("build[dir={}]", "x".repeat(301))));
+    }
+
+    #[test]
+    fn test_take_builtin() {
+        let task_names = &["build", "hash2"];
+
+        let mut args = vec_of_strings!["otto", "-j", "2", "hash", "build"];
+        assert_eq!(take_builtin(&mut args, task_names), Some(Builtin::Hash));
+        assert_eq!(args, vec_of_strings!["otto", "-j", "2", "build"]);
+
+        let mut args = vec_of_strings!["otto", "build", "hash"];
+        assert_eq!(take_builtin(&mut args, task_names), None, "builtins come before tasks");
+        assert_eq!(args, vec_of_strings!["otto", "build", "hash"]);
+
+        let mut args = vec_of_strings!["otto", "hash"];
+        assert_eq!(take_builtin(&mut args, &["hash"]), None, "tasks shadow builtins");
+
+        let mut args = vec_of_strings!["otto", "cache", "prune", "--max-size", "1G", "build"];
         assert_eq!(
-            partitions(&args, &task_names),
-            vec![vec!["arg1"], vec!["task1", "arg2"], vec!["task2", "arg3"]]
+            take_builtin(&mut args, task_names),
+            Some(Builtin::Cache(vec_of_strings!["prune", "--max-size", "1G", "build"]))
+        );
+        assert_eq!(args, vec_of_strings!["otto"]);
+
+        let mut args = vec_of_strings!["otto", "cache-server", "--listen", "0.0.0.0:9000"];
+        assert_eq!(
+            take_builtin(&mut args, task_names),
+            Some(Builtin::CacheServer(vec_of_strings!["--listen", "0.0.0.0:9000"]))
         );
     }
 
@@ -516,6 +1466,13 @@ mod tests {
             jobs: num_cpus::get(),
             verbosity: "1".to_string(),
             tasks: vec!["build".to_string()],
+            keep_going: false,
+            output: OutputMode::Interleaved,
+            grace_period: Duration::from_secs(5),
+            shell: "sh".to_string(),
+            dry_run: false,
+            env_prefix: String::new(),
+            invocation_dir: None,
         }
     }
 
@@ -524,12 +1481,41 @@ mod tests {
             name: "build".to_string(),
             help: Some("Build the project".to_string()),
             params: HashMap::new(),
-            before: vec![],
-            after: vec![],
+            requires: vec![],
+            order_after: vec![],
             action: "echo 'building'".to_string(),
+            cmd: vec![],
+            dir: None,
+            show: true,
+            inputs: vec![],
+            outputs: vec![],
+            cache: true,
+            timeout: None,
+            retries: 0,
+            retry_delay: None,
+            shell: None,
+            passthrough: false,
         }
     }
 
+    #[test]
+    fn test_grace_period_default_is_a_duration() {
+        let mut otto = generate_test_otto();
+        otto.grace_period = Duration::from_millis(1500);
+        let mut command = Parser::otto_to_command(&otto, &HashMap::new());
+        assert!(command.render_help().to_string().contains("[default: 1500ms]"));
+        let matches = command.try_get_matches_from(["otto"]).unwrap();
+        assert_eq!(matches.get_one::<Duration>("grace-period"), Some(&Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_invocation_dir_option() {
+        let ottofile = Ottofile::new("ottofile-invocation-dir", "tasks:\n  build: {}\n");
+        assert_eq!(ottofile.parse(&["build"]).unwrap().0.invocation_dir, None);
+        let (otto, _, _) = ottofile.parse(&["--invocation-dir", "/tmp/work", "build"]).unwrap();
+        assert_eq!(otto.invocation_dir, Some(PathBuf::from("/tmp/work")));
+    }
+
     #[test]
     fn test_handle_no_input_no_ottofile() {
         let args = vec![];
@@ -556,23 +1542,25 @@ mod tests {
         println!("generated otto: {otto:#?}");
 
         let args = vec!["otto".to_string()];
-        let pargs = partitions(&args, &["build"]);
+        let pargs = vec![args.clone()];
 
         let mut parser = Parser {
             hash: DEFAULT_HASH.to_string(),
+            ottofile: None,
             prog: "otto".to_string(),
             cwd: env::current_dir().unwrap(),
-            user: env::var("USER").unwrap(),
+            user: env::var("USER").unwrap_or_default(),
             config: Config {
                 otto,
                 tasks: HashMap::new(),
             },
             args,
             pargs,
+            builtin: None,
         };
 
-        let result = parser.parse();
-        assert!(result.is_err());
+        let err = parser.parse().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(OttoParseError::NoTasks)), "{err}");
     }
 
     #[test]
@@ -584,19 +1572,21 @@ mod tests {
         tasks.insert(task.name.clone(), task);
 
         let args = vec!["otto".to_string(), "build".to_string()];
-        let pargs = partitions(&args, &["build"]);
+        let pargs = vec![vec!["otto".to_string()], vec!["build".to_string()]];
 
         let mut parser = Parser {
             prog: "otto".to_string(),
             hash: DEFAULT_HASH.to_string(),
+            ottofile: None,
             cwd: env::current_dir().unwrap(),
-            user: env::var("USER").unwrap(),
+            user: env::var("USER").unwrap_or_default(),
             config: Config {
                 otto: otto.clone(),
                 tasks: tasks.clone(),
             },
             args,
             pargs,
+            builtin: None,
         };
 
         let result = parser.parse().unwrap();
@@ -614,4 +1604,325 @@ mod tests {
         // Assert job name
         assert_eq!(first_task.name, "build".to_string(), "comparing task name");
     }
+
+    #[test]
+    fn test_task_values_follow_nargs() {
+        let yaml = r#"
+params:
+  -o|--one: {}
+  -z|--zero:
+    nargs: 0
+  -m|--maybe:
+    nargs: "?"
+    constant: fast
+  -p|--plus:
+    nargs: "+"
+  -s|--star:
+    nargs: "*"
+  -e|--exact:
+    nargs: 2
+  -r|--range:
+    nargs: "2:3"
+    default: only
+"#;
+        let mut task: Task = serde_yaml::from_str(yaml).unwrap();
+        task.name = "build".to_string();
+        let item = |s: &str| Value::Item(s.to_string());
+
+        let args = ["-o", "1", "-z", "-m", "-p", "a", "b", "-s", "-e", "x", "y", "-r", "q", "r", "s"];
+        let given = values(&task, &args).unwrap();
+        assert_eq!(given["one"], item("1"));
+        assert_eq!(given["zero"], item("true"));
+        assert_eq!(given["maybe"], item("fast"));
+        assert_eq!(given["plus"], Value::List(vec_of_strings!["a", "b"]));
+        assert_eq!(given["star"], Value::List(vec![]));
+        assert_eq!(given["exact"], Value::List(vec_of_strings!["x", "y"]));
+        assert_eq!(given["range"], Value::List(vec_of_strings!["q", "r", "s"]));
+
+        let defaults = values(&task, &["-m", "slow"]).unwrap();
+        assert_eq!(defaults["maybe"], item("slow"));
+        assert_eq!(defaults["range"], Value::List(vec_of_strings!["only"]));
+        assert!(!defaults.contains_key("zero"));
+        assert!(!defaults.contains_key("plus"));
+
+        assert!(values(&task, &["-o"]).is_err());
+        assert!(values(&task, &["-o", "1", "2"]).is_err());
+        assert!(values(&task, &["-p"]).is_err());
+        assert!(values(&task, &["-e", "x"]).is_err());
+        assert!(values(&task, &["-r", "q"]).is_err());
+        assert!(values(&task, &["-r", "q", "r", "s", "t"]).is_err());
+    }
+
+    #[test]
+    fn test_task_values_for_flags_and_positionals() {
+        let yaml = r#"
+params:
+  src: {}
+  -v|--verbose:
+    default: "false"
+  -q|--quiet:
+    default: "true"
+  -c|--color:
+    nargs: 0
+    constant: always
+  dst:
+    nargs: "*"
+  -p|--profile:
+    default: dev
+"#;
+        let mut task: Task = serde_yaml::from_str(yaml).unwrap();
+        task.name = "copy".to_string();
+        let item = |s: &str| Value::Item(s.to_string());
+
+        let given = values(&task, &["a", "-v", "b", "-q", "--color", "c", "-p", "release"]).unwrap();
+        assert_eq!(given["src"], item("a"));
+        assert_eq!(given["dst"], Value::List(vec_of_strings!["b", "c"]));
+        assert_eq!(given["verbose"], item("true"));
+        assert_eq!(given["quiet"], item("false"));
+        assert_eq!(given["color"], item("always"));
+        assert_eq!(given["profile"], item("release"));
+
+        let bare = values(&task, &["a"]).unwrap();
+        assert!(!bare.contains_key("verbose"));
+        assert!(!bare.contains_key("dst"));
+        assert_eq!(bare["profile"], item("dev"));
+
+        // The source is required, flags take no values and options do
+        assert!(values(&task, &[]).is_err());
+        assert!(values(&task, &["a", "--verbose=true"]).is_err());
+        assert!(values(&task, &["a", "-p"]).is_err());
+    }
+
+    #[test]
+    fn test_task_values_honor_choices_dest_and_metavar() {
+        let yaml = r#"
+params:
+  -g|--greeting:
+    choices: [howdy, hello]
+    default: hello
+    metavar: WORD
+    dest: GREETING
+    help: the greeting
+"#;
+        let mut task: Task = serde_yaml::from_str(yaml).unwrap();
+        task.name = "hello".to_string();
+
+        assert_eq!(values(&task, &["-g", "howdy"]).unwrap()["GREETING"], Value::Item("howdy".to_string()));
+        assert_eq!(values(&task, &[]).unwrap()["GREETING"], Value::Item("hello".to_string()));
+        assert!(values(&task, &["-g", "anything"]).is_err());
+
+        let help = Parser::task_to_command(&task).render_help().to_string();
+        assert!(help.contains("--greeting <WORD>"), "{help}");
+        assert!(help.contains("[possible values: howdy, hello]"), "{help}");
+    }
+
+    #[test]
+    fn test_check_params() {
+        let task = |yaml: &str| {
+            let mut task: Task = serde_yaml::from_str(yaml).unwrap();
+            task.name = "deploy".to_string();
+            task
+        };
+
+        let good = task("params:\n  --dry-run: {default: \"false\"}\n  -e|--env: {dest: target}\n  src: {}\n");
+        assert!(Parser::check_params(&good, "").is_ok());
+        assert!(Parser::check_params(&good, "OTTO_ARG_").is_ok());
+        assert!(Parser::check_params(&good, "1").is_err());
+
+        let values = values(&good, &["--dry-run", "-e", "prod", "app"]).unwrap();
+        assert_eq!(values["dry_run"], Value::Item("true".to_string()));
+        assert_eq!(values["target"], Value::Item("prod".to_string()));
+
+        let colliding = task("params:\n  --dry-run: {}\n  --dry_run: {}\n");
+        let err = Parser::check_params(&colliding, "").unwrap_err().to_string();
+        assert!(err.contains("both be exported as dry_run"), "{err}");
+
+        let invalid = task("params:\n  source file: {}\n");
+        let err = Parser::check_params(&invalid, "").unwrap_err().to_string();
+        assert!(err.contains("not a valid environment variable name"), "{err}");
+
+        let bad_default = task("params:\n  -g: {choices: [a, b], default: c}\n");
+        assert!(Parser::check_params(&bad_default, "").is_err());
+
+        // What clap would reject when it builds the command
+        let err = Parser::check_params(&task("params:\n  -e|--env: {}\n  -e|--exec: {}\n"), "").unwrap_err();
+        assert!(err.to_string().contains("both use -e"), "{err}");
+        assert!(Parser::check_params(&task("params:\n  -h|--host: {}\n"), "").is_err());
+        assert!(Parser::check_params(&task("params:\n  --help: {}\n"), "").is_err());
+        let err = Parser::check_params(&task("params:\n  src: {nargs: \"?\"}\n  dst: {}\n"), "").unwrap_err();
+        assert!(err.to_string().contains("dst is required but follows the optional positional src"), "{err}");
+        assert!(Parser::check_params(&task("params:\n  src: {}\n  dst: {default: out}\n"), "").is_ok());
+
+        let mut variadic = task("params:\n  files: {nargs: \"*\"}\n");
+        assert!(Parser::check_params(&variadic, "").is_ok());
+        Parser::task_to_command(&variadic).build();
+        variadic.passthrough = true;
+        let err = Parser::check_params(&variadic, "").unwrap_err().to_string();
+        assert!(err.contains("param files takes several values"), "{err}");
+        let mut options = task("params:\n  --files: {nargs: \"+\"}\n  src: {}\n");
+        options.passthrough = true;
+        assert!(Parser::check_params(&options, "").is_ok());
+        Parser::task_to_command(&options).build();
+    }
+
+    #[test]
+    fn test_task_values_pass_trailing_arguments_through() {
+        let mut task: Task = serde_yaml::from_str("params:\n  -p|--profile: {default: dev}\n").unwrap();
+        task.name = "test".to_string();
+        let (values, args) = parse_task(&task, &["-p", "release", "--", "--nocapture", "-p", "x"]).unwrap();
+        assert_eq!(values["profile"], Value::Item("release".to_string()));
+        assert_eq!(args, vec_of_strings!["--nocapture", "-p", "x"]);
+        assert!(parse_task(&task, &["-p", "release"]).unwrap().1.is_empty());
+        assert!(parse_task(&task, &["--nocapture"]).is_err());
+
+        task.passthrough = true;
+        let (values, args) = parse_task(&task, &["-p", "release", "--nocapture", "-p", "x"]).unwrap();
+        assert_eq!(values["profile"], Value::Item("release".to_string()));
+        assert_eq!(args, vec_of_strings!["--nocapture", "-p", "x"]);
+        assert_eq!(parse_task(&task, &["file", "--", "x"]).unwrap().1, vec_of_strings!["file", "--", "x"]);
+    }
+
+    #[test]
+    fn test_task_dirs_are_relative_to_the_ottofile() {
+        let yaml = "tasks:\n  root:\n    action: pwd\n  docs:\n    dir: docs\n    action: pwd\n";
+        let ottofile = Ottofile::new("ottofile-dir", yaml);
+        let mut parser = ottofile.parser(&["root", "docs"]);
+        let dir = fs::canonicalize(ottofile.dir()).unwrap();
+        assert_eq!(parser.project_dir(), dir);
+
+        let (_, dag, _) = parser.parse().unwrap();
+        let task = |name: &str| dag.raw_nodes().iter().find(|node| node.weight.name == name).unwrap().weight.clone();
+        assert_eq!(task("root").dir, Some(dir.clone()));
+        assert_eq!(task("docs").dir, Some(dir.join("docs")));
+    }
+
+    #[test]
+    fn test_repeated_tasks_get_a_node_each() {
+        let yaml = "tasks:\n  build: {}\n  deploy:\n    before: [build]\n    params:\n      -r|--region: {default: us}\n  \
+                    notify:\n    before: [deploy]\n";
+        let ottofile = Ottofile::new("ottofile-repeated", yaml);
+        let args = ["deploy", "-r", "eu", "deploy", "notify", "deploy", "-r", "ap"];
+        let (otto, dag, _) = ottofile.parse(&args).unwrap();
+        assert_eq!(otto.tasks, vec_of_strings!["deploy#1", "deploy#2", "notify", "deploy#3"]);
+        assert_eq!(dag.node_count(), 5);
+
+        let index = |name: &str| dag.graph().node_indices().find(|index| dag[*index].name == name).unwrap();
+        let region = |name: &str| dag[index(name)].values["region"].clone();
+        assert_eq!(region("deploy#1"), Value::Item("eu".to_string()));
+        assert_eq!(region("deploy#2"), Value::Item("us".to_string()));
+        assert_eq!(region("deploy#3"), Value::Item("ap".to_string()));
+
+        // The instances share their dependency, and the dependent waits for all of them
+        for deploy in ["deploy#1", "deploy#2", "deploy#3"] {
+            assert_eq!(dag[index(deploy)].deps, vec_of_strings!["build"]);
+            assert!(dag.find_edge(index("build"), index(deploy)).is_some());
+            assert!(dag.find_edge(index(deploy), index("notify")).is_some());
+        }
+        let mut deps = dag[index("notify")].deps.clone();
+        deps.sort();
+        assert_eq!(deps, vec_of_strings!["deploy#1", "deploy#2", "deploy#3"]);
+    }
+
+    #[test]
+    fn test_requires_and_order_after() {
+        let yaml = r#"
+tasks:
+  build: {}
+  lint: {}
+  test:
+    requires:
+    - build
+    - task: codegen
+      optional: true
+  cleanup:
+    order_after: [build, lint, {task: docs, optional: true}]
+"#;
+        let ottofile = Ottofile::new("ottofile-order-after", yaml);
+        let (_, dag, _) = ottofile.parse(&["test", "cleanup"]).unwrap();
+        let index = |name: &str| dag.graph().node_indices().find(|index| dag[*index].name == name).unwrap();
+        let edge = |from: &str, to: &str| dag.find_edge(index(from), index(to)).map(|edge| dag[edge]);
+        assert_eq!(dag[index("test")].deps, vec_of_strings!["build"]);
+        assert!(dag[index("cleanup")].deps.is_empty());
+        assert_eq!(edge("build", "test"), Some(Edge::Requires));
+        assert_eq!(edge("build", "cleanup"), Some(Edge::OrderAfter));
+        assert_eq!(edge("lint", "cleanup"), Some(Edge::OrderAfter));
+
+        ottofile.write("tasks:\n  cleanup:\n    order_after: [docs]\n");
+        let err = ottofile.parse(&["cleanup"]).unwrap_err().to_string();
+        assert!(err.contains("task cleanup depends on unknown task docs"), "{err}");
+    }
+
+    #[test]
+    fn test_deps_with_args_get_a_node_each() {
+        let yaml = r#"
+tasks:
+  fetch: {}
+  build:
+    before: [fetch]
+    params:
+      -p|--profile: {default: debug, choices: [debug, release]}
+      --locked: {default: "false"}
+      -t|--targets: {nargs: "*"}
+      --cargo-features: {dest: features}
+  test:
+    before: [build]
+  package:
+    before:
+    - task: build
+      args: {profile: release, locked: true}
+  publish:
+    before:
+    - package
+    - task: build
+      args: {locked: true, profile: release}
+  check:
+    before:
+    - task: build
+      args: {profile: debug, locked: false}
+  lint:
+    before:
+    - task: build
+      args: {targets: "x y"}
+    - task: build
+      args: {targets: [x, y]}
+  doc:
+    before:
+    - task: build
+      args: {features: a/b}
+    - task: build
+      args: {cargo-features: a/b}
+"#;
+        let ottofile = Ottofile::new("ottofile-dep-args", yaml);
+        let (_, dag, _) = ottofile.parse(&["test", "publish", "check", "lint", "doc"]).unwrap();
+        assert_eq!(dag.node_count(), 12);
+
+        let index = |name: &str| dag.graph().node_indices().find(|index| dag[*index].name == name).unwrap();
+        let release = "build[locked=true,profile=release]";
+        assert_eq!(dag[index("build")].values["profile"], Value::Item("debug".to_string()));
+        assert_eq!(dag[index(release)].values["profile"], Value::Item("release".to_string()));
+        assert_eq!(dag[index(release)].values["locked"], Value::Item("true".to_string()));
+        assert_eq!(dag[index("test")].deps, vec_of_strings!["build"]);
+        assert_eq!(dag[index("package")].deps, vec_of_strings![release]);
+        assert_eq!(dag[index("publish")].deps, vec_of_strings!["package", release]);
+        assert_eq!(dag[index(release)].deps, vec_of_strings!["fetch"]);
+        assert!(dag.find_edge(index("fetch"), index(release)).is_some());
+
+        // Nodes are told apart by the values the args resolve to, not by how the args are written
+        assert_eq!(dag[index("check")].deps, vec_of_strings!["build"]);
+        let mut lint = dag[index("lint")].deps.clone();
+        lint.sort();
+        assert_eq!(lint, vec_of_strings![r#"build[targets=["x y"]]"#, r#"build[targets=["x","y"]]"#]);
+        assert_eq!(dag[index("doc")].deps, vec_of_strings!["build[features=a/b]"]);
+        assert_eq!(dag[index("build[features=a/b]")].file_stem(), "build[features=a%2Fb]");
+
+        let invalid = |args: &str| {
+            let yaml = format!("tasks:\n  build:\n    params:\n      -p|--profile: {{choices: [debug, release]}}\n  \
+                                package:\n    before:\n    - task: build\n      args: {args}\n");
+            ottofile.write(&yaml);
+            ottofile.parse(&["package"]).unwrap_err().to_string()
+        };
+        assert!(invalid("{target: x86}").contains("task build has no param target"));
+        let err = invalid("{profile: fast}");
+        assert!(err.contains("task package depends on build[profile=fast]: invalid value 'fast'"), "{err}");
+    }
 }
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use eyre::{eyre, Result};
+
+use crate::cfg::param::Value;
+
+/// Resolves the `cmd` of a task into the arguments to execute, replacing every `${name}` with the
+/// value of the parameter `name`; `$$` is a literal `$`, and any other `$` is left alone.
+///
+/// An argument that is nothing but a placeholder for a list becomes one argument per item, or none
+/// if the parameter has no value, so optional flags can be passed through without quoting. Inside
+/// a longer argument, list items are joined with spaces.
+///
+/// # Errors
+///
+/// This function will return an error if a placeholder is not closed or names an unknown parameter.
+pub fn interpolate(cmd: &[String], values: &HashMap<String, Value>) -> Result<Vec<String>> {
+    let mut argv = vec![];
+    for arg in cmd {
+        let whole = arg.strip_prefix("${").and_then(|rest| rest.strip_suffix('}'));
+        match whole.filter(|name| !name.contains('}')).map(|name| lookup(name, values)) {
+            Some(value) => match value? {
+                Value::Item(item) => argv.push(item.clone()),
+                Value::List(items) => argv.extend(items.iter().cloned()),
+                Value::Dict(dict) => argv.extend(sorted_pairs(dict)),
+                Value::Empty => {}
+            },
+            None => argv.push(substitute(arg, values)?),
+        }
+    }
+    Ok(argv)
+}
+
+fn lookup<'a>(name: &str, values: &'a HashMap<String, Value>) -> Result<&'a Value> {
+    values.get(name).ok_or_else(|| eyre!("unknown parameter in cmd: ${{{}}}", name))
+}
+
+fn sorted_pairs(dict: &HashMap<String, String>) -> Vec<String> {
+    let mut pairs: Vec<String> = dict.iter().map(|(key, value)| format!("{key}={value}")).collect();
+    pairs.sort();
+    pairs
+}
+
+fn substitute(arg: &str, values: &HashMap<String, Value>) -> Result<String> {
+    let mut result = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(position) = rest.find('$') {
+        result.push_str(&rest[..position]);
+        rest = &rest[position..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| eyre!("unclosed placeholder in cmd: {}", arg))?;
+            match lookup(&after[..end], values)? {
+                Value::Item(item) => result.push_str(item),
+                Value::List(items) => result.push_str(&items.join(" ")),
+                Value::Dict(dict) => result.push_str(&sorted_pairs(dict).join(" ")),
+                Value::Empty => {}
+            }
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Quotes `arg` for a POSIX shell, leaving it as it is if that is unambiguous.
+#[must_use]
+pub fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// The command line that runs `argv`, as it could be pasted into a shell.
+#[must_use]
+pub fn display(argv: &[String]) -> String {
+    argv.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        let values = HashMap::from([
+            ("profile".to_string(), Value::Item("release".to_string())),
+            ("message".to_string(), Value::Item("it's done".to_string())),
+            ("features".to_string(), Value::List(vec_of_strings!["a", "b c"])),
+            ("extra".to_string(), Value::Empty),
+        ]);
+        let cmd = vec_of_strings!["cargo", "build", "--${profile}", "${features}", "${extra}", "-m", "${message}", "$$HOME", "$1"];
+        assert_eq!(
+            interpolate(&cmd, &values).unwrap(),
+            vec_of_strings!["cargo", "build", "--release", "a", "b c", "-m", "it's done", "$HOME", "$1"]
+        );
+        assert_eq!(interpolate(&vec_of_strings!["[${features}]"], &values).unwrap(), vec_of_strings!["[a b c]"]);
+        assert!(interpolate(&vec_of_strings!["${missing}"], &values).is_err());
+        assert!(interpolate(&vec_of_strings!["--${profile"], &values).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(display(&vec_of_strings!["cargo", "test", "--workspace"]), "cargo test --workspace");
+        assert_eq!(display(&vec_of_strings!["echo", "it's done", ""]), r"echo 'it'\''s done' ''");
+    }
+}
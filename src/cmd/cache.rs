@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use clap::{value_parser, Arg, Command};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// One output file stored in the cache.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedFile {
+    pub path: String,
+    pub blob: String,
+    pub mode: u32,
+}
+
+/// The outputs of one task execution, stored under the task's fingerprint.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<CachedFile>,
+}
+
+impl Manifest {
+    /// Checks that every file names a hex SHA-256 blob and a relative path that stays below the
+    /// directory it is restored into, since manifests may come from a remote cache.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a blob name or a path is not safe to use.
+    pub fn validate(&self) -> Result<()> {
+        for file in &self.files {
+            if !is_digest(&file.blob) {
+                return Err(eyre!("invalid blob name in manifest: {}", file.blob));
+            }
+            if file.path.is_empty() || !is_below(Path::new(&file.path)) {
+                return Err(eyre!("invalid path in manifest: {}", file.path));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub blobs: usize,
+    pub size: u64,
+}
+
+/// A content-addressed store of task outputs under `<home>/cache`: file contents live in `cas/`
+/// named by their SHA-256, and `ac/<key>.json` manifests map a task fingerprint to those files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cache {
+    root: PathBuf,
+}
+
+/// Whether `s` is a lowercase hex SHA-256 digest, the form of every cache key and blob name.
+#[must_use]
+pub fn is_digest(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Whether the relative `path` stays below the directory it is joined to.
+fn is_below(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Writes `bytes` to `path` via a temporary file, so readers never see a partial file.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Lists the files below `path`, or `path` itself if it is a file.
+fn walk(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(fs::DirEntry::file_name);
+        for entry in entries {
+            walk(&entry.path(), files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Parses a size such as `500M`, `2G` or `1024`, using binary multiples.
+///
+/// # Errors
+///
+/// This function will return an error if `size` is not a number with an optional K, M, G or T suffix.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = size[digits.len()..].to_ascii_uppercase();
+    let multiplier: u64 = match suffix.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(eyre!("invalid size: {}", size)),
+    };
+    let number: u64 = digits.trim().parse().map_err(|_| eyre!("invalid size: {}", size))?;
+    Ok(number * multiplier)
+}
+
+impl Cache {
+    #[must_use]
+    pub fn new(home: &Path) -> Self {
+        Self::at(home.join("cache"))
+    }
+
+    /// A cache stored directly in `root`, rather than in the `cache` directory of an Otto home.
+    #[must_use]
+    pub const fn at(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn manifest_path(&self, key: &str) -> PathBuf {
+        self.root.join("ac").join(format!("{key}.json"))
+    }
+
+    fn blob_path(&self, blob: &str) -> PathBuf {
+        self.root.join("cas").join(blob)
+    }
+
+    fn ensure_dirs(&self) -> Result<()> {
+        fs::create_dir_all(self.root.join("ac"))?;
+        fs::create_dir_all(self.root.join("cas"))?;
+        Ok(())
+    }
+
+    /// Returns the manifest stored under `key`, whether or not its blobs are present.
+    #[must_use]
+    pub fn manifest(&self, key: &str) -> Option<Manifest> {
+        serde_json::from_slice(&fs::read(self.manifest_path(key)).ok()?).ok()
+    }
+
+    /// Returns the content of a blob, if it is present.
+    #[must_use]
+    pub fn blob(&self, blob: &str) -> Option<Vec<u8>> {
+        fs::read(self.blob_path(blob)).ok()
+    }
+
+    #[must_use]
+    pub fn has_blob(&self, blob: &str) -> bool {
+        self.blob_path(blob).exists()
+    }
+
+    /// Returns the manifest stored under `key`, if there is one and all of its blobs are present.
+    #[must_use]
+    pub fn lookup(&self, key: &str) -> Option<Manifest> {
+        let path = self.manifest_path(key);
+        let manifest = self.manifest(key)?;
+        if !manifest.files.iter().all(|file| self.has_blob(&file.blob)) {
+            return None;
+        }
+        // Record the hit, so that pruning evicts the least recently used entries first
+        if let Ok(file) = File::options().append(true).open(&path) {
+            file.set_modified(SystemTime::now()).ok();
+        }
+        Some(manifest)
+    }
+
+    /// Writes the files of `manifest` back into place, relative to `dir`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the manifest is invalid, a blob cannot be read or a file
+    /// cannot be written.
+    pub fn restore(&self, manifest: &Manifest, dir: &Path) -> Result<()> {
+        manifest.validate()?;
+        for file in &manifest.files {
+            let path = dir.join(&file.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            fs::copy(self.blob_path(&file.blob), &path)?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(file.mode))?;
+        }
+        Ok(())
+    }
+
+    /// Stores the files below each of `outputs`, relative to `dir`, under `key`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an output does not exist, is outside `dir` or cannot be
+    /// read, or the cache cannot be written.
+    pub fn store(&self, key: &str, outputs: &[String], dir: &Path) -> Result<Manifest> {
+        self.ensure_dirs()?;
+        let mut manifest = Manifest::default();
+        for output in outputs {
+            let path = dir.join(output);
+            if !path.exists() {
+                return Err(eyre!("output {} was not created", output));
+            }
+            let mut files = vec![];
+            walk(&path, &mut files)?;
+            for file in files {
+                let relative = file
+                    .strip_prefix(dir)
+                    .ok()
+                    .filter(|relative| is_below(relative))
+                    .ok_or_else(|| eyre!("output {} is outside the task directory", output))?;
+                let content = fs::read(&file)?;
+                let blob = sha256_hex(&content);
+                self.put_blob(&blob, &content)?;
+                manifest.files.push(CachedFile {
+                    path: relative.to_string_lossy().to_string(),
+                    blob,
+                    mode: fs::metadata(&file)?.permissions().mode(),
+                });
+            }
+        }
+        self.put_manifest(key, &manifest)?;
+        Ok(manifest)
+    }
+
+    /// Stores a blob under its content hash, unless it is already present.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `content` does not hash to `blob` or cannot be written.
+    pub fn put_blob(&self, blob: &str, content: &[u8]) -> Result<()> {
+        if sha256_hex(content) != blob {
+            return Err(eyre!("blob content does not match its hash: {}", blob));
+        }
+        let path = self.blob_path(blob);
+        if !path.exists() {
+            self.ensure_dirs()?;
+            write_atomic(&path, content)?;
+        }
+        Ok(())
+    }
+
+    /// Stores `manifest` under `key`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the manifest cannot be written.
+    pub fn put_manifest(&self, key: &str, manifest: &Manifest) -> Result<()> {
+        self.ensure_dirs()?;
+        write_atomic(&self.manifest_path(key), &serde_json::to_vec_pretty(manifest)?)
+    }
+
+    fn manifests(&self) -> Result<Vec<(PathBuf, Manifest)>> {
+        let dir = self.root.join("ac");
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut manifests = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let manifest = serde_json::from_slice(&fs::read(&path)?).unwrap_or_default();
+                manifests.push((path, manifest));
+            }
+        }
+        Ok(manifests)
+    }
+
+    fn blobs(&self) -> Result<HashMap<String, u64>> {
+        let dir = self.root.join("cas");
+        if !dir.exists() {
+            return Ok(HashMap::new());
+        }
+        let mut blobs = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            blobs.insert(entry.file_name().to_string_lossy().to_string(), entry.metadata()?.len());
+        }
+        Ok(blobs)
+    }
+
+    /// Counts the entries and blobs in the cache and their total size on disk.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache directory cannot be read.
+    pub fn stats(&self) -> Result<CacheStats> {
+        let manifests = self.manifests()?;
+        let blobs = self.blobs()?;
+        let mut size: u64 = blobs.values().sum();
+        for (path, _) in &manifests {
+            size += fs::metadata(path)?.len();
+        }
+        Ok(CacheStats {
+            entries: manifests.len(),
+            blobs: blobs.len(),
+            size,
+        })
+    }
+
+    /// Evicts the least recently used entries, and the blobs only they refer to, until the cache is
+    /// no larger than `max_size` bytes. Blobs no entry refers to are always removed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the cache directory cannot be read or a file cannot be removed.
+    pub fn prune(&self, max_size: u64) -> Result<CacheStats> {
+        let manifests = self.manifests()?;
+        let blobs = self.blobs()?;
+
+        let mut references: HashMap<&str, usize> = HashMap::new();
+        for (_, manifest) in &manifests {
+            for file in &manifest.files {
+                *references.entry(file.blob.as_str()).or_default() += 1;
+            }
+        }
+        let mut size = 0;
+        for (blob, blob_size) in &blobs {
+            if references.contains_key(blob.as_str()) {
+                size += blob_size;
+            } else {
+                fs::remove_file(self.blob_path(blob))?;
+            }
+        }
+
+        let mut entries = vec![];
+        for (path, manifest) in &manifests {
+            let metadata = fs::metadata(path)?;
+            size += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), path, manifest));
+        }
+        entries.sort_by_key(|(modified, ..)| *modified);
+
+        for (_, manifest_size, path, manifest) in entries {
+            if size <= max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            size -= manifest_size;
+            for file in &manifest.files {
+                let count = references.get_mut(file.blob.as_str()).expect("blob is referenced");
+                *count -= 1;
+                if *count == 0 {
+                    fs::remove_file(self.blob_path(&file.blob))?;
+                    size -= blobs.get(&file.blob).copied().unwrap_or_default();
+                }
+            }
+        }
+
+        self.stats()
+    }
+}
+
+fn cache_command() -> Command {
+    Command::new("cache")
+        .bin_name("otto cache")
+        .about("inspect and prune the local task output cache")
+        .subcommand_required(true)
+        .subcommand(Command::new("stats").about("show the number of cached entries and their size"))
+        .subcommand(
+            Command::new("prune")
+                .about("evict least recently used entries until the cache fits")
+                .arg(
+                    Arg::new("max-size")
+                        .long("max-size")
+                        .value_name("SIZE")
+                        .required(true)
+                        .value_parser(value_parser!(String))
+                        .help("maximum cache size, e.g. 500M or 2G"),
+                ),
+        )
+}
+
+/// Runs `otto cache <stats|prune>` against the cache in `home`.
+///
+/// # Errors
+///
+/// This function will return an error if the arguments are invalid or the cache cannot be read or pruned.
+pub fn run(home: &Path, args: &[String]) -> Result<()> {
+    let cache = Cache::new(home);
+    let matches = cache_command().try_get_matches_from(std::iter::once("cache".to_string()).chain(args.iter().cloned()))?;
+    let stats = match matches.subcommand() {
+        Some(("prune", matches)) => {
+            let max_size = matches.get_one::<String>("max-size").expect("max-size is required");
+            cache.prune(parse_size(max_size)?)?
+        }
+        _ => cache.stats()?,
+    };
+    println!("entries: {}", stats.entries);
+    println!("blobs:   {}", stats.blobs);
+    println!("size:    {}", format_size(stats.size));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse_size("3gb").unwrap(), 3 << 30);
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("5X").is_err());
+    }
+
+    #[test]
+    fn test_store_and_restore() {
+        let dir = TempDir::with_dirs("cache-restore", &["work/gen"]);
+        let work = dir.join("work");
+        let cache = Cache::new(&dir);
+        fs::write(work.join("gen/a.rs"), "fn a() {}").unwrap();
+        fs::write(work.join("gen/b.rs"), "fn b() {}").unwrap();
+        fs::write(work.join("out.bin"), [0u8, 159, 146, 150]).unwrap();
+        fs::set_permissions(work.join("out.bin"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(cache.lookup("key").is_none());
+        let outputs = vec!["gen".to_string(), "out.bin".to_string()];
+        let manifest = cache.store("key", &outputs, &work).unwrap();
+        assert_eq!(manifest.files.len(), 3);
+        assert!(cache.store("other", &["missing".to_string()], &work).is_err());
+        assert!(cache.store("other", &["../work/out.bin".to_string()], &work).is_err());
+        assert!(cache.store("other", &[dir.to_string_lossy().to_string()], &work).is_err());
+
+        fs::remove_dir_all(&work).unwrap();
+        cache.restore(&cache.lookup("key").unwrap(), &work).unwrap();
+        assert_eq!(fs::read_to_string(work.join("gen/a.rs")).unwrap(), "fn a() {}");
+        assert_eq!(fs::read_to_string(work.join("gen/b.rs")).unwrap(), "fn b() {}");
+        assert_eq!(fs::read(work.join("out.bin")).unwrap(), vec![0u8, 159, 146, 150]);
+        assert_eq!(fs::metadata(work.join("out.bin")).unwrap().permissions().mode() & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = TempDir::with_dirs("cache-prune", &["work/gen"]);
+        let work = dir.join("work");
+        let cache = Cache::new(&dir);
+        fs::write(work.join("gen/shared"), "shared").unwrap();
+        fs::write(work.join("gen/old"), "x".repeat(4096)).unwrap();
+        cache.store("old", &["gen".to_string()], &work).unwrap();
+        fs::remove_file(work.join("gen/old")).unwrap();
+        cache.store("new", &["gen".to_string()], &work).unwrap();
+        File::options()
+            .append(true)
+            .open(cache.manifest_path("old"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.entries, stats.blobs), (2, 2));
+
+        let stats = cache.prune(stats.size - 1).unwrap();
+        assert_eq!((stats.entries, stats.blobs), (1, 1), "the oldest entry and its blob are evicted");
+        assert!(cache.lookup("new").is_some());
+        assert!(cache.lookup("old").is_none());
+
+        let stats = cache.prune(0).unwrap();
+        assert_eq!(stats, CacheStats::default());
+    }
+
+    #[test]
+    fn test_restore_rejects_unsafe_manifests() {
+        let dir = TempDir::with_dirs("cache-unsafe", &["work/gen"]);
+        let work = dir.join("work");
+        let cache = Cache::new(&dir);
+        fs::write(work.join("out"), "out").unwrap();
+        let manifest = cache.store("key", &["out".to_string()], &work).unwrap();
+        let blob = manifest.files[0].blob.clone();
+
+        for (path, blob) in [
+            ("../escaped", blob.as_str()),
+            ("gen/../../escaped", blob.as_str()),
+            ("/tmp/escaped", blob.as_str()),
+            ("", blob.as_str()),
+            ("out", "../../../etc/passwd"),
+            ("out", "ABC"),
+        ] {
+            let manifest = Manifest {
+                files: vec![CachedFile {
+                    path: path.to_string(),
+                    blob: blob.to_string(),
+                    mode: 0o644,
+                }],
+            };
+            assert!(cache.restore(&manifest, &work).is_err(), "{path} {blob}");
+        }
+        assert!(!dir.join("escaped").exists());
+        cache.restore(&manifest, &work).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use eyre::{eyre, Result};
+
+use crate::cfg::param::Value;
+
+/// What the items of a list are joined with in its plain variable.
+pub const LIST_DELIMITER: &str = " ";
+
+/// Whether `name` can be the name of an environment variable in a shell: an ASCII letter or
+/// underscore, followed by ASCII letters, digits and underscores.
+#[must_use]
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Turns `key` into something that can be part of a variable name: every character other than
+/// an ASCII letter, digit or underscore becomes an underscore.
+fn sanitize(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// The environment variables that carry the parameter `name` with `value` into a task:
+///
+/// - an item is `NAME`
+/// - a list is `NAME` with the items joined by [`LIST_DELIMITER`], `NAME_0` to `NAME_<n-1>`,
+///   `NAME_COUNT` and `NAME_JSON`, a JSON array
+/// - a dict is `NAME_<KEY>` for every key, `NAME_KEYS` with the keys joined by [`LIST_DELIMITER`],
+///   and `NAME_JSON`, a JSON object; characters in keys that cannot appear in a variable name
+///   become underscores
+/// - an empty value sets nothing
+#[must_use]
+pub fn encode(name: &str, value: &Value) -> Vec<(String, String)> {
+    match value {
+        Value::Item(item) => vec![(name.to_string(), item.clone())],
+        Value::List(items) => {
+            let mut vars = vec![
+                (name.to_string(), items.join(LIST_DELIMITER)),
+                (format!("{name}_COUNT"), items.len().to_string()),
+                (format!("{name}_JSON"), serde_json::to_string(items).unwrap_or_default()),
+            ];
+            vars.extend(items.iter().enumerate().map(|(i, item)| (format!("{name}_{i}"), item.clone())));
+            vars
+        }
+        Value::Dict(dict) => {
+            let mut keys: Vec<&String> = dict.keys().collect();
+            keys.sort();
+            let mut vars = vec![
+                (format!("{name}_KEYS"), keys.iter().map(|key| key.as_str()).collect::<Vec<_>>().join(LIST_DELIMITER)),
+                (format!("{name}_JSON"), serde_json::to_string(dict).unwrap_or_default()),
+            ];
+            vars.extend(keys.iter().map(|key| (format!("{name}_{}", sanitize(key)), dict[*key].clone())));
+            vars
+        }
+        Value::Empty => vec![],
+    }
+}
+
+/// The variables for every value in `values`, as [`encode`] names them after `prefix`.
+///
+/// # Errors
+///
+/// This function will return an error if two values would set the same variable, such as a list
+/// `files` and a value named `files_COUNT`, or the dict keys `a-b` and `a_b`.
+pub fn encode_all(prefix: &str, values: &HashMap<String, Value>) -> Result<HashMap<String, String>> {
+    let mut names: Vec<&String> = values.keys().collect();
+    names.sort();
+    let mut vars = HashMap::new();
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    for name in names {
+        for (var, value) in encode(&format!("{prefix}{name}"), &values[name]) {
+            if let Some(owner) = owners.insert(var.clone(), name) {
+                let what = if owner == name {
+                    format!("{name} has two keys that")
+                } else {
+                    format!("{owner} and {name}")
+                };
+                return Err(eyre!("{} would both set the environment variable {}", what, var));
+            }
+            vars.insert(var, value);
+        }
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("greeting", &Value::Item("hi".to_string())), vec![("greeting".to_string(), "hi".to_string())]);
+        assert_eq!(encode("extra", &Value::Empty), vec![]);
+
+        let list: HashMap<String, String> = encode("files", &Value::List(vec_of_strings!["a.txt", "b c.txt"])).into_iter().collect();
+        assert_eq!(list["files"], "a.txt b c.txt");
+        assert_eq!(list["files_0"], "a.txt");
+        assert_eq!(list["files_1"], "b c.txt");
+        assert_eq!(list["files_COUNT"], "2");
+        assert_eq!(list["files_JSON"], r#"["a.txt","b c.txt"]"#);
+        assert_eq!(list.len(), 5);
+
+        let dict = HashMap::from([("db-host".to_string(), "localhost".to_string()), ("port".to_string(), "5432".to_string())]);
+        let dict: HashMap<String, String> = encode("conn", &Value::Dict(dict)).into_iter().collect();
+        assert_eq!(dict["conn_db_host"], "localhost");
+        assert_eq!(dict["conn_port"], "5432");
+        assert_eq!(dict["conn_KEYS"], "db-host port");
+        let json: HashMap<String, String> = serde_json::from_str(&dict["conn_JSON"]).unwrap();
+        assert_eq!(json["db-host"], "localhost");
+        assert_eq!(dict.len(), 4);
+    }
+
+    #[test]
+    fn test_encode_all() {
+        let values = HashMap::from([("greeting".to_string(), Value::Item("hi".to_string()))]);
+        assert_eq!(encode_all("", &values).unwrap()["greeting"], "hi");
+        assert_eq!(encode_all("OTTO_ARG_", &values).unwrap()["OTTO_ARG_greeting"], "hi");
+
+        // Whichever value would win a shared variable depends on the order of a HashMap
+        let values = HashMap::from([
+            ("files".to_string(), Value::List(vec_of_strings!["a", "b"])),
+            ("files_COUNT".to_string(), Value::Item("7".to_string())),
+        ]);
+        let err = encode_all("", &values).unwrap_err().to_string();
+        assert_eq!(err, "files and files_COUNT would both set the environment variable files_COUNT");
+        let dict = HashMap::from([("JSON".to_string(), "x".to_string())]);
+        let values = HashMap::from([("conn".to_string(), Value::Dict(dict))]);
+        assert!(encode_all("", &values).is_err());
+        let dict = HashMap::from([("a-b".to_string(), "x".to_string()), ("a_b".to_string(), "y".to_string())]);
+        let values = HashMap::from([("conn".to_string(), Value::Dict(dict))]);
+        let err = encode_all("", &values).unwrap_err().to_string();
+        assert_eq!(err, "conn has two keys that would both set the environment variable conn_a_b");
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("dry_run"));
+        assert!(is_valid_name("_x1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("1st"));
+        assert!(!is_valid_name("dry-run"));
+        assert!(!is_valid_name("src file"));
+    }
+}
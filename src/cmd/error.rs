@@ -1 +1,137 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaskStatus {
+    Succeeded,
+    UpToDate,
+    Cached,
+    Failed(String),
+    Skipped(String),
+    Cancelled,
+}
+
+impl TaskStatus {
+    /// A short name for the status, as written to the run directory.
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::UpToDate => "up-to-date",
+            Self::Cached => "cached",
+            Self::Failed(_) => "failed",
+            Self::Skipped(_) => "skipped",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// The outcome of every selected task, in DAG order, and how many attempts each task that was
+/// started took.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub tasks: Vec<(String, TaskStatus)>,
+    pub attempts: HashMap<String, u32>,
+}
+
+impl RunSummary {
+    #[must_use]
+    pub fn succeeded(&self) -> Vec<&str> {
+        self.filter(|status| matches!(status, TaskStatus::Succeeded))
+    }
+
+    #[must_use]
+    pub fn up_to_date(&self) -> Vec<&str> {
+        self.filter(|status| matches!(status, TaskStatus::UpToDate))
+    }
+
+    #[must_use]
+    pub fn cached(&self) -> Vec<&str> {
+        self.filter(|status| matches!(status, TaskStatus::Cached))
+    }
+
+    #[must_use]
+    pub fn failed(&self) -> Vec<&str> {
+        self.filter(|status| matches!(status, TaskStatus::Failed(_)))
+    }
+
+    #[must_use]
+    pub fn skipped(&self) -> Vec<&str> {
+        self.filter(|status| matches!(status, TaskStatus::Skipped(_)))
+    }
+
+    #[must_use]
+    pub fn cancelled(&self) -> Vec<&str> {
+        self.filter(|status| matches!(status, TaskStatus::Cancelled))
+    }
+
+    /// The tasks that needed more than one attempt, with the number of attempts.
+    #[must_use]
+    pub fn retried(&self) -> Vec<(&str, u32)> {
+        self.tasks
+            .iter()
+            .filter_map(|(name, _)| self.attempts.get(name).filter(|n| **n > 1).map(|n| (name.as_str(), *n)))
+            .collect()
+    }
+
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.tasks
+            .iter()
+            .all(|(_, status)| matches!(status, TaskStatus::Succeeded | TaskStatus::UpToDate | TaskStatus::Cached))
+    }
+
+    fn filter(&self, predicate: impl Fn(&TaskStatus) -> bool) -> Vec<&str> {
+        self.tasks
+            .iter()
+            .filter(|(_, status)| predicate(status))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let unsuccessful = self.tasks.len() - self.succeeded().len() - self.up_to_date().len() - self.cached().len();
+        write!(f, "{} of {} tasks did not succeed", unsuccessful, self.tasks.len())?;
+        for (name, status) in &self.tasks {
+            match status {
+                TaskStatus::Failed(reason) => write!(f, "\n  failed:    {name} ({reason})")?,
+                TaskStatus::Skipped(reason) => write!(f, "\n  skipped:   {name} (because {reason})")?,
+                TaskStatus::Cancelled => write!(f, "\n  cancelled: {name}")?,
+                TaskStatus::Succeeded | TaskStatus::UpToDate | TaskStatus::Cached => {}
+            }
+        }
+        let succeeded = self.succeeded();
+        if !succeeded.is_empty() {
+            write!(f, "\n  succeeded: {}", succeeded.join(", "))?;
+        }
+        let up_to_date = self.up_to_date();
+        if !up_to_date.is_empty() {
+            write!(f, "\n  up to date: {}", up_to_date.join(", "))?;
+        }
+        let cached = self.cached();
+        if !cached.is_empty() {
+            write!(f, "\n  restored from cache: {}", cached.join(", "))?;
+        }
+        let retried: Vec<String> = self.retried().iter().map(|(name, n)| format!("{name} ({n} attempts)")).collect();
+        if !retried.is_empty() {
+            write!(f, "\n  retried: {}", retried.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SchedulerError {
+    #[error("{0}")]
+    RunFailed(RunSummary),
+    #[error("run cancelled: {0}")]
+    Cancelled(RunSummary),
+    #[error("Task {0} was interrupted")]
+    Interrupted(String),
+    #[error("Task {0} was aborted because another task failed")]
+    Aborted(String),
+}
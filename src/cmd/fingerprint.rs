@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use daggy::petgraph::algo::toposort;
+use daggy::{NodeIndex, Walker};
+use eyre::{eyre, Result};
+use glob::glob;
+use sha2::{Digest, Sha256};
+
+use crate::cli::parse::{file_stem, Edge, TaskSpec, DAG};
+
+/// Expands the input globs of a task, relative to `dir`, into a sorted list of files.
+///
+/// # Errors
+///
+/// This function will return an error if a pattern is invalid or a matched path cannot be read.
+pub fn expand_inputs(patterns: &[String], dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        let pattern = dir.join(pattern);
+        let pattern = pattern.to_str().ok_or_else(|| eyre!("input pattern is not valid UTF-8: {}", pattern.display()))?;
+        for entry in glob(pattern)? {
+            let path = entry?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Hashes everything that decides whether a task's outputs are current: its action, its parameter
+/// values, the arguments passed through to it and its environment, its declared outputs, the content of every input file, and the
+/// fingerprints of the tasks it depends on. Because dependency fingerprints are folded in, a change
+/// anywhere upstream changes the fingerprint of every task downstream of it.
+///
+/// Inputs are found relative to the task's own directory, or to `dir` if it has none, and hashed
+/// under their path relative to it, so that the same project gets the same fingerprints wherever
+/// it is checked out.
+///
+/// # Errors
+///
+/// This function will return an error if an input pattern is invalid or an input file cannot be read.
+pub fn fingerprint(task: &TaskSpec, dir: &Path, deps: &[&str]) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("action:{}\n", task.hash));
+    for arg in &task.cmd {
+        hasher.update(format!("cmd:{arg}\n"));
+    }
+    if let Some(shell) = &task.shell {
+        hasher.update(format!("shell:{shell}\n"));
+    }
+    hash_values(&mut hasher, task)?;
+    let mut envs: Vec<_> = task.envs.iter().collect();
+    envs.sort();
+    for (name, value) in envs {
+        hasher.update(format!("env:{name}={value}\n"));
+    }
+    for output in &task.outputs {
+        hasher.update(format!("output:{output}\n"));
+    }
+    let root = task.dir.as_deref().unwrap_or(dir);
+    for input in expand_inputs(&task.inputs, root)? {
+        let content = fs::read(&input)?;
+        let path = input.strip_prefix(root).unwrap_or(&input);
+        hasher.update(format!("input:{}:{}\n", path.display(), hex::encode(Sha256::digest(content))));
+    }
+    let mut deps = deps.to_vec();
+    deps.sort_unstable();
+    for dep in deps {
+        hasher.update(format!("dep:{dep}\n"));
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hashes the values of the task's params and the arguments passed through to it.
+fn hash_values(hasher: &mut Sha256, task: &TaskSpec) -> Result<()> {
+    for arg in &task.args {
+        hasher.update(format!("arg:{arg}\n"));
+    }
+    let mut values: Vec<_> = task.values.iter().collect();
+    values.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, value) in values {
+        hasher.update(format!("value:{name}={}\n", serde_json::to_string(value)?));
+    }
+    Ok(())
+}
+
+/// Sets the fingerprint of every `selected` task in `dag`, dependencies first. The tasks a selected
+/// task requires have to be selected too; other tasks are left alone, so that their inputs are
+/// never read.
+///
+/// # Errors
+///
+/// This function will return an error if the DAG has a cycle or a fingerprint cannot be computed.
+pub fn fingerprint_dag(dag: &mut DAG<TaskSpec>, dir: &Path, selected: &HashSet<NodeIndex>) -> Result<()> {
+    let order = toposort(dag.graph(), None).map_err(|_| eyre!("Circular dependency detected"))?;
+    for index in order.into_iter().filter(|index| selected.contains(index)) {
+        dag[index].fingerprint = fingerprint(&dag[index], dir, &parent_fingerprints(dag, index))?;
+    }
+    Ok(())
+}
+
+/// The fingerprints of the tasks the task at `index` requires; those it is only ordered after
+/// have no say in its outputs.
+#[must_use]
+pub fn parent_fingerprints(dag: &DAG<TaskSpec>, index: NodeIndex) -> Vec<&str> {
+    dag.parents(index)
+        .iter(dag)
+        .filter(|(edge, _)| dag[*edge] == Edge::Requires)
+        .map(|(_, parent)| dag[parent].fingerprint.as_str())
+        .collect()
+}
+
+/// Where the fingerprint of the task's last successful run is kept: under the name of the task and
+/// a hash of its values, rather than the name of its node, which for a task given more than once
+/// depends on where it is on the command line. The same task with the same values finds it again
+/// in any run.
+fn fingerprint_path(task: &TaskSpec, store: &Path) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hash_values(&mut hasher, task).ok();
+    let values = hex::encode(hasher.finalize());
+    store.join(format!("{}-{}.fingerprint", file_stem(&task.task), &values[..16]))
+}
+
+/// A task is up to date if it declares inputs or outputs, all of its outputs exist, and `fingerprint`
+/// matches the one stored after its last successful run.
+#[must_use]
+pub fn is_up_to_date(task: &TaskSpec, dir: &Path, store: &Path, fingerprint: &str) -> bool {
+    if task.inputs.is_empty() && task.outputs.is_empty() {
+        return false;
+    }
+    if !task.outputs.iter().all(|output| dir.join(output).exists()) {
+        return false;
+    }
+    fs::read_to_string(fingerprint_path(task, store)).is_ok_and(|stored| stored.trim() == fingerprint)
+}
+
+/// Stores `fingerprint` as the one of the task's last successful run.
+///
+/// # Errors
+///
+/// This function will return an error if the fingerprint cannot be written.
+pub fn store(task: &TaskSpec, store: &Path, fingerprint: &str) -> Result<()> {
+    fs::write(fingerprint_path(task, store), fingerprint)?;
+    Ok(())
+}
+
+/// Forgets the stored fingerprint, so that a task which fails or is interrupted is never considered
+/// up to date.
+///
+/// # Errors
+///
+/// This function will return an error if an existing fingerprint cannot be removed.
+pub fn invalidate(task: &TaskSpec, store: &Path) -> Result<()> {
+    let path = fingerprint_path(task, store);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::cfg::param::Value;
+    use std::collections::HashMap;
+
+    fn generate_test_spec() -> TaskSpec {
+        let mut spec = TaskSpec::new("gen".to_string(), vec![], HashMap::new(), HashMap::new(), "cp src/* out\n".to_string());
+        spec.inputs = vec!["src/*.txt".to_string()];
+        spec.outputs = vec!["out".to_string()];
+        spec
+    }
+
+    #[test]
+    fn test_fingerprint_tracks_inputs_and_values() {
+        let dir = TempDir::with_dirs("fingerprint", &["src"]);
+        fs::write(dir.join("src/a.txt"), "a").unwrap();
+        let mut spec = generate_test_spec();
+
+        let first = fingerprint(&spec, &dir, &[]).unwrap();
+        assert_eq!(first, fingerprint(&spec, &dir, &[]).unwrap());
+
+        fs::write(dir.join("src/a.txt"), "changed").unwrap();
+        let second = fingerprint(&spec, &dir, &[]).unwrap();
+        assert_ne!(first, second);
+
+        fs::write(dir.join("src/b.txt"), "new").unwrap();
+        let third = fingerprint(&spec, &dir, &[]).unwrap();
+        assert_ne!(second, third);
+
+        spec.values.insert("mode".to_string(), Value::Item("release".to_string()));
+        let fourth = fingerprint(&spec, &dir, &[]).unwrap();
+        assert_ne!(third, fourth);
+
+        spec.envs.insert("CC".to_string(), "clang".to_string());
+        let fifth = fingerprint(&spec, &dir, &[]).unwrap();
+        assert_ne!(fourth, fifth);
+
+        assert_ne!(fifth, fingerprint(&spec, &dir, &["abc"]).unwrap());
+        assert_eq!(
+            fingerprint(&spec, &dir, &["abc", "def"]).unwrap(),
+            fingerprint(&spec, &dir, &["def", "abc"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_does_not_depend_on_where_the_project_is() {
+        let here = TempDir::with_dirs("fingerprint-here", &["src"]);
+        let there = TempDir::with_dirs("fingerprint-there", &["src"]);
+        let mut spec = generate_test_spec();
+        let fingerprints: Vec<String> = [&here, &there]
+            .iter()
+            .map(|dir| {
+                fs::write(dir.join("src/a.txt"), "a").unwrap();
+                spec.dir = Some(dir.to_path_buf());
+                fingerprint(&spec, Path::new("/"), &[]).unwrap()
+            })
+            .collect();
+        assert_eq!(fingerprints[0], fingerprints[1]);
+
+        fs::write(here.join("src/b.txt"), "b").unwrap();
+        fs::write(there.join("src/c.txt"), "b").unwrap();
+        spec.dir = None;
+        assert_ne!(fingerprint(&spec, &here, &[]).unwrap(), fingerprint(&spec, &there, &[]).unwrap(), "input names count");
+    }
+
+    #[test]
+    fn test_fingerprint_dag_includes_dependencies() {
+        let dir = TempDir::with_dirs("fingerprint-dag", &["src"]);
+        let spec = |name: &str, action: &str| {
+            TaskSpec::new(name.to_string(), vec![], HashMap::new(), HashMap::new(), action.to_string())
+        };
+        let mut dag: DAG<TaskSpec> = DAG::new();
+        let build = dag.add_node(spec("build", "make"));
+        let test = dag.add_node(spec("test", "make test"));
+        let lint = dag.add_node(spec("lint", "make lint"));
+        dag.add_edge(build, test, Edge::Requires).unwrap();
+        dag.add_edge(build, lint, Edge::OrderAfter).unwrap();
+
+        let mut broken = spec("broken", "true");
+        broken.inputs = vec!["[".to_string()];
+        let broken = dag.add_node(broken);
+        let selected = HashSet::from([build, test, lint]);
+
+        fingerprint_dag(&mut dag, &dir, &selected).unwrap();
+        let (test_before, lint_before) = (dag[test].fingerprint.clone(), dag[lint].fingerprint.clone());
+        assert_eq!(test_before.len(), 64);
+        assert!(dag[broken].fingerprint.is_empty(), "tasks that are not selected are left alone");
+
+        dag[build] = spec("build", "make --release");
+        fingerprint_dag(&mut dag, &dir, &selected).unwrap();
+        assert_ne!(dag[test].fingerprint, test_before, "upstream change");
+        assert_eq!(dag[lint].fingerprint, lint_before, "task only ordered after it");
+        assert!(fingerprint_dag(&mut dag, &dir, &HashSet::from([broken])).is_err());
+    }
+
+    #[test]
+    fn test_is_up_to_date() {
+        let dir = TempDir::with_dirs("up-to-date", &["src"]);
+        fs::write(dir.join("src/a.txt"), "a").unwrap();
+        let spec = generate_test_spec();
+        let fp = fingerprint(&spec, &dir, &[]).unwrap();
+
+        store(&spec, &dir, &fp).unwrap();
+        assert!(!is_up_to_date(&spec, &dir, &dir, &fp), "missing outputs");
+
+        fs::write(dir.join("out"), "a").unwrap();
+        assert!(is_up_to_date(&spec, &dir, &dir, &fp));
+        assert!(!is_up_to_date(&spec, &dir, &dir, "other"));
+
+        invalidate(&spec, &dir).unwrap();
+        assert!(!is_up_to_date(&spec, &dir, &dir, &fp));
+
+        let plain = TaskSpec::new("plain".to_string(), vec![], HashMap::new(), HashMap::new(), String::new());
+        assert!(!is_up_to_date(&plain, &dir, &dir, &fp), "tasks without inputs or outputs always run");
+        // Stored fingerprints follow the task and its values, not the name of its node
+        store(&spec, &dir, &fp).unwrap();
+        let mut second = spec.clone();
+        second.name = "gen#2".to_string();
+        assert!(is_up_to_date(&second, &dir, &dir, &fp));
+        second.values.insert("mode".to_string(), Value::Item("release".to_string()));
+        assert!(!is_up_to_date(&second, &dir, &dir, &fp));
+        store(&second, &dir, &fp).unwrap();
+        invalidate(&second, &dir).unwrap();
+        assert!(is_up_to_date(&spec, &dir, &dir, &fp), "other values have a fingerprint of their own");
+    }
+}
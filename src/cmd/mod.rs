@@ -1,2 +1,11 @@
+pub mod argv;
+pub mod cache;
+pub mod env;
 pub mod error;
-pub mod scheduler;
\ No newline at end of file
+pub mod fingerprint;
+pub mod output;
+pub mod process;
+pub mod record;
+pub mod remote;
+pub mod scheduler;
+pub mod script;
\ No newline at end of file
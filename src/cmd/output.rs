@@ -0,0 +1,194 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::cfg::otto::OutputMode;
+
+const COLORS: &[&str] = &["36", "33", "32", "35", "34", "96", "93", "92", "95", "94"];
+const RESET: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn is_terminal(self) -> bool {
+        match self {
+            Self::Stdout => io::stdout().is_terminal(),
+            Self::Stderr => io::stderr().is_terminal(),
+        }
+    }
+
+    fn write_all(self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Stdout => io::stdout().lock().write_all(bytes),
+            Self::Stderr => io::stderr().lock().write_all(bytes),
+        }
+    }
+}
+
+/// The label written in front of every line of a task's output, e.g. `build | `.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prefix {
+    label: String,
+    color: &'static str,
+}
+
+impl Prefix {
+    /// Pads `name` to `width` so the output of concurrent tasks lines up, and picks a color by `index`.
+    #[must_use]
+    pub fn new(name: &str, width: usize, index: usize) -> Self {
+        Self {
+            label: format!("{name:<width$} | "),
+            color: COLORS[index % COLORS.len()],
+        }
+    }
+
+    /// Returns `line` with the prefix in front, optionally colored. The line itself is passed through
+    /// untouched, but always ends with a newline, so that an unterminated last line does not run into
+    /// the next task's prefix. The task's logs keep its exact bytes.
+    #[must_use]
+    pub fn apply(&self, line: &[u8], color: bool) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.label.len() + line.len() + 16);
+        if color {
+            bytes.extend_from_slice(format!("\x1b[{}m{}{RESET}", self.color, self.label).as_bytes());
+        } else {
+            bytes.extend_from_slice(self.label.as_bytes());
+        }
+        bytes.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            bytes.push(b'\n');
+        }
+        bytes
+    }
+}
+
+/// Where the lines of one task go: straight to the terminal, or into a buffer that is printed in
+/// one piece when the task finishes.
+#[derive(Debug)]
+pub struct Sink {
+    prefix: Prefix,
+    live: bool,
+    show_on_success: bool,
+    buffer: Mutex<Vec<(Stream, Vec<u8>)>>,
+}
+
+impl Sink {
+    /// Chooses live or buffered output for a task from the run's `mode` and the task's `show` setting.
+    /// Buffered output is always shown if the task fails.
+    #[must_use]
+    pub fn new(prefix: Prefix, mode: OutputMode, show: bool) -> Self {
+        Self {
+            prefix,
+            live: mode == OutputMode::Interleaved && show,
+            show_on_success: mode == OutputMode::Grouped && show,
+            buffer: Mutex::new(vec![]),
+        }
+    }
+
+    fn write(&self, line: &[u8], stream: Stream, color: bool) -> io::Result<()> {
+        let bytes = self.prefix.apply(line, color);
+        if self.live {
+            stream.write_all(&bytes)
+        } else {
+            self.buffer.lock().expect("output buffer poisoned").push((stream, bytes));
+            Ok(())
+        }
+    }
+
+    /// Prints the buffered lines, if any should be shown, without interleaving them with other output.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if writing to stdout or stderr fails.
+    pub fn finish(&self, success: bool) -> io::Result<()> {
+        if self.live || (success && !self.show_on_success) {
+            return Ok(());
+        }
+        let buffer = std::mem::take(&mut *self.buffer.lock().expect("output buffer poisoned"));
+        let mut stdout = io::stdout().lock();
+        let mut stderr = io::stderr().lock();
+        for (stream, bytes) in buffer {
+            match stream {
+                Stream::Stdout => stdout.write_all(&bytes)?,
+                Stream::Stderr => stderr.write_all(&bytes)?,
+            }
+        }
+        stdout.flush()?;
+        stderr.flush()
+    }
+}
+
+/// Copies `reader` to `sink` line by line as it is produced, and unprefixed to `log`. Prefixes are
+/// colored when `stream` is a terminal.
+///
+/// # Errors
+///
+/// This function will return an error if reading from `reader` or writing to `stream` or `log` fails.
+pub async fn stream_lines<R, W>(reader: R, sink: &Sink, stream: Stream, log: &mut W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let color = stream.is_terminal();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return log.flush().await;
+        }
+        log.write_all(&line).await?;
+        sink.write(&line, stream, color)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_apply() {
+        let prefix = Prefix::new("build", 8, 0);
+        assert_eq!(prefix.apply(b"hello\n", false), b"build    | hello\n".to_vec());
+        assert_eq!(prefix.apply(b"no newline", false), b"build    | no newline\n".to_vec());
+        assert_eq!(prefix.apply(b"\xff\xfe\n", false), b"build    | \xff\xfe\n".to_vec());
+        assert_eq!(prefix.apply(b"hi\n", true), b"\x1b[36mbuild    | \x1b[0mhi\n".to_vec());
+    }
+
+    #[test]
+    fn test_sink_modes() {
+        let prefix = Prefix::new("t", 1, 0);
+        let sink = |mode, show| Sink::new(prefix.clone(), mode, show);
+
+        let interleaved = sink(OutputMode::Interleaved, true);
+        assert!(interleaved.live);
+
+        let hidden = sink(OutputMode::Interleaved, false);
+        assert!(!hidden.live && !hidden.show_on_success);
+
+        let grouped = sink(OutputMode::Grouped, true);
+        assert!(!grouped.live && grouped.show_on_success);
+        grouped.write(b"line\n", Stream::Stdout, false).unwrap();
+        assert_eq!(grouped.buffer.lock().unwrap().len(), 1);
+
+        let quiet = sink(OutputMode::Quiet, true);
+        assert!(!quiet.live && !quiet.show_on_success);
+        quiet.write(b"line\n", Stream::Stderr, false).unwrap();
+        quiet.finish(true).unwrap();
+        assert_eq!(quiet.buffer.lock().unwrap().len(), 1, "quiet output of a successful task is not shown");
+    }
+
+    #[tokio::test]
+    async fn test_stream_lines_logs_a_missing_final_newline_as_is() {
+        let sink = Sink::new(Prefix::new("t", 1, 0), OutputMode::Grouped, true);
+        let mut log = Vec::new();
+        stream_lines(&b"one\ntwo"[..], &sink, Stream::Stdout, &mut log).await.unwrap();
+        assert_eq!(log, b"one\ntwo".to_vec());
+        let shown: Vec<u8> = sink.buffer.lock().unwrap().iter().flat_map(|(_, bytes)| bytes.clone()).collect();
+        assert_eq!(shown, b"t | one\nt | two\n".to_vec());
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+
+use tokio::process::Child;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// The name of a signal otto forwards to its tasks.
+#[must_use]
+pub const fn signal_name(signal: libc::c_int) -> &'static str {
+    match signal {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        _ => "signal",
+    }
+}
+
+/// Catches SIGINT and SIGTERM from now on and publishes each one on `sender`, instead of letting
+/// them terminate otto and leave its tasks running.
+///
+/// # Errors
+///
+/// This function will return an error if the signal handlers cannot be installed.
+pub fn forward_signals(sender: watch::Sender<Option<libc::c_int>>) -> io::Result<JoinHandle<()>> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(tokio::spawn(async move {
+        loop {
+            let signal = tokio::select! {
+                _ = interrupt.recv() => libc::SIGINT,
+                _ = terminate.recv() => libc::SIGTERM,
+            };
+            if sender.send(Some(signal)).is_err() {
+                return;
+            }
+        }
+    }))
+}
+
+/// The process group a task runs in. The task's shell is started as the group leader, so
+/// everything it spawns can be signalled together.
+#[derive(Debug)]
+pub struct ProcessGroup {
+    pgid: libc::pid_t,
+    armed: bool,
+}
+
+impl ProcessGroup {
+    /// The group led by `child`, which must have been spawned with `process_group(0)`.
+    /// Returns `None` if the child has already been reaped.
+    #[must_use]
+    pub fn of(child: &Child) -> Option<Self> {
+        let pgid = libc::pid_t::try_from(child.id()?).ok()?;
+        Some(Self { pgid, armed: true })
+    }
+
+    /// Sends `signal` to every process in the group.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the group no longer exists or cannot be signalled.
+    pub fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        // SAFETY: killpg has no memory safety requirements
+        if unsafe { libc::killpg(self.pgid, signal) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Kills every process in the group.
+    pub fn kill(&self) {
+        // The group may already be gone, which is what we want anyway
+        self.signal(libc::SIGKILL).ok();
+    }
+
+    /// Leaves the group alone when it is dropped, once the task has exited on its own.
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ProcessGroup {
+    /// A task whose future is dropped before it exits, because it timed out or the run was
+    /// cancelled, takes everything it started down with it.
+    fn drop(&mut self) {
+        if self.armed {
+            self.kill();
+        }
+    }
+}
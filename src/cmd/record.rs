@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+use serde::Serialize;
+
+use crate::cfg::param::Value;
+use crate::cli::parse::{file_stem, TaskSpec};
+use crate::cmd::error::{RunSummary, SchedulerError};
+
+fn epoch_secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// Everything about one task's execution, written to `<task>.json` in the run directory next to
+/// `<task>.stdout` and `<task>.stderr`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TaskRecord {
+    pub name: String,
+    pub status: String,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+    pub started_at: f64,
+    pub finished_at: f64,
+    pub duration: f64,
+    pub attempts: u32,
+    pub hash: String,
+    pub env: HashMap<String, String>,
+    pub values: HashMap<String, Value>,
+}
+
+impl TaskRecord {
+    #[must_use]
+    pub fn new(
+        task: &TaskSpec,
+        env: HashMap<String, String>,
+        started: SystemTime,
+        finished: SystemTime,
+        attempts: u32,
+        result: &Result<ExitStatus>,
+    ) -> Self {
+        let (status, exit_code, error) = match result {
+            Ok(status) if status.success() => ("succeeded", status.code(), None),
+            Ok(status) => ("failed", status.code(), None),
+            Err(err) if matches!(err.downcast_ref(), Some(SchedulerError::Interrupted(_))) => {
+                ("cancelled", None, Some(err.to_string()))
+            }
+            Err(err) if matches!(err.downcast_ref(), Some(SchedulerError::Aborted(_))) => {
+                ("aborted", None, Some(err.to_string()))
+            }
+            Err(err) => ("failed", None, Some(err.to_string())),
+        };
+        Self {
+            name: task.name.clone(),
+            status: status.to_string(),
+            exit_code,
+            error,
+            started_at: epoch_secs(started),
+            finished_at: epoch_secs(finished),
+            duration: finished.duration_since(started).map_or(0.0, |d| d.as_secs_f64()),
+            attempts,
+            hash: task.hash.clone(),
+            env,
+            values: task.values.clone(),
+        }
+    }
+
+    /// Writes the record as `<task>.json` into the run directory `dir`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the record cannot be serialized or written.
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(dir.join(format!("{}.json", file_stem(&self.name))), json)?;
+        Ok(())
+    }
+}
+
+/// The outcome of a whole run, written to `.run.json` in the run directory: `succeeded`, `failed`,
+/// or `cancelled` together with the signal that interrupted it. The leading dot keeps it apart from
+/// the task records, whose file names never start with one.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunRecord {
+    pub status: String,
+    pub signal: Option<String>,
+    pub started_at: f64,
+    pub finished_at: f64,
+    pub tasks: BTreeMap<String, String>,
+}
+
+impl RunRecord {
+    #[must_use]
+    pub fn new(summary: &RunSummary, signal: Option<&str>, started: SystemTime, finished: SystemTime) -> Self {
+        let status = match signal {
+            Some(_) => "cancelled",
+            None if summary.is_success() => "succeeded",
+            None => "failed",
+        };
+        Self {
+            status: status.to_string(),
+            signal: signal.map(ToString::to_string),
+            started_at: epoch_secs(started),
+            finished_at: epoch_secs(finished),
+            tasks: summary
+                .tasks
+                .iter()
+                .map(|(name, status)| (name.clone(), status.as_str().to_string()))
+                .collect(),
+        }
+    }
+
+    /// Writes the record as `.run.json` into the run directory `dir`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the record cannot be serialized or written.
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(dir.join(".run.json"), json)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,564 @@
+//! A shared cache of task outputs over plain HTTP/1.1.
+//!
+//! The protocol mirrors the layout of the local [`Cache`]:
+//!
+//! - `GET /ac/<key>` returns the JSON manifest stored under a task fingerprint, or 404
+//! - `PUT /ac/<key>` stores a manifest
+//! - `GET /cas/<sha256>` returns the content of a blob, or 404
+//! - `PUT /cas/<sha256>` stores a blob; the server rejects content that does not match the hash
+//!
+//! Keys and blob names are lowercase hex SHA-256 digests. Every request uses its own connection.
+//! A server only accepts `PUT` requests when it was started with writes enabled, and bounds the
+//! size of the requests and the number of connections it handles at once: see [`Limits`].
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{value_parser, Arg, ArgAction, Command};
+use eyre::{eyre, Result};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+use crate::cmd::cache::{is_digest, Cache, Manifest};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The largest response the client reads, which bounds the size of a cached output.
+const MAX_RESPONSE: usize = 1 << 30;
+/// The longest start or header line of a message, and the most headers it may have.
+const MAX_LINE: usize = 8 << 10;
+const MAX_HEADERS: usize = 100;
+const DEFAULT_LISTEN: &str = "127.0.0.1:8000";
+
+/// How much a cache server takes on at once, so that no client can exhaust its memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The largest request body accepted, in bytes.
+    pub max_body: usize,
+    /// How many connections are handled at the same time; further ones wait to be accepted.
+    pub max_connections: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_body: 64 << 20,
+            max_connections: 64,
+        }
+    }
+}
+
+/// A client for a remote cache at an `http://host[:port][/prefix]` URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteCache {
+    authority: String,
+    prefix: String,
+}
+
+impl RemoteCache {
+    /// Parses the URL of a remote cache.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `url` is not an `http://` URL with a host.
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| eyre!("remote cache url must start with http://: {}", url))?;
+        let (authority, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if authority.is_empty() {
+            return Err(eyre!("remote cache url has no host: {}", url));
+        }
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        let prefix = prefix.trim_end_matches('/');
+        let prefix = if prefix.is_empty() { String::new() } else { format!("/{prefix}") };
+        Ok(Self { authority, prefix })
+    }
+
+    /// The remote cache configured by the `api` setting, if it holds an `http://` URL.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `api` looks like a URL but cannot be used as one.
+    pub fn from_api(api: &str) -> Result<Option<Self>> {
+        if api.starts_with("http://") {
+            Self::parse(api).map(Some)
+        } else if api.starts_with("https://") {
+            Err(eyre!("https is not supported for the remote cache: {}", api))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<(u16, Vec<u8>)> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(&self.authority).await?;
+            let head = format!(
+                "{method} {}{path} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                self.prefix,
+                self.authority,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(body).await?;
+            let mut response = BufReader::new(stream);
+            let (status, _, body) = read_message(&mut response, MAX_RESPONSE).await?;
+            let status = status
+                .split_whitespace()
+                .nth(1)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| eyre!("invalid response from remote cache: {}", status))?;
+            Ok((status, body))
+        };
+        tokio::time::timeout(TIMEOUT, exchange)
+            .await
+            .map_err(|_| eyre!("remote cache request timed out: {} {}", method, path))?
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match self.request("GET", path, &[]).await? {
+            (200, body) => Ok(Some(body)),
+            (404, _) => Ok(None),
+            (status, _) => Err(eyre!("remote cache returned {} for GET {}", status, path)),
+        }
+    }
+
+    async fn put(&self, path: &str, body: &[u8]) -> Result<()> {
+        match self.request("PUT", path, body).await? {
+            (200..=299, _) => Ok(()),
+            (status, _) => Err(eyre!("remote cache returned {} for PUT {}", status, path)),
+        }
+    }
+
+    /// Downloads the entry stored under `key` into the local `cache`, fetching only the blobs it
+    /// does not have yet. Returns `None` if the remote cache has no complete entry.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the remote cache cannot be reached or returns invalid data.
+    pub async fn fetch(&self, key: &str, cache: &Cache) -> Result<Option<Manifest>> {
+        let Some(body) = self.get(&format!("/ac/{key}")).await? else {
+            return Ok(None);
+        };
+        let manifest: Manifest = serde_json::from_slice(&body)?;
+        manifest.validate()?;
+        for file in &manifest.files {
+            if cache.has_blob(&file.blob) {
+                continue;
+            }
+            let Some(content) = self.get(&format!("/cas/{}", file.blob)).await? else {
+                return Ok(None);
+            };
+            cache.put_blob(&file.blob, &content)?;
+        }
+        cache.put_manifest(key, &manifest)?;
+        Ok(Some(manifest))
+    }
+
+    /// Uploads the entry stored under `key` in the local `cache`: its blobs first, then the manifest,
+    /// so that other clients never see a manifest whose blobs are missing.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the remote cache cannot be reached or rejects the upload.
+    pub async fn upload(&self, key: &str, manifest: &Manifest, cache: &Cache) -> Result<()> {
+        let mut uploaded = HashSet::new();
+        for file in &manifest.files {
+            if !uploaded.insert(&file.blob) {
+                continue;
+            }
+            let content = cache
+                .blob(&file.blob)
+                .ok_or_else(|| eyre!("blob missing from local cache: {}", file.blob))?;
+            self.put(&format!("/cas/{}", file.blob), &content).await?;
+        }
+        self.put(&format!("/ac/{key}"), &serde_json::to_vec(manifest)?).await
+    }
+}
+
+/// Reads one line of at most [`MAX_LINE`] bytes, returning how many were read.
+async fn read_line<R>(reader: &mut R, line: &mut String) -> Result<usize>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let read = reader.take(MAX_LINE as u64).read_line(line).await?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(eyre!("line too long: more than {} bytes", MAX_LINE));
+    }
+    Ok(read)
+}
+
+/// Reads an HTTP/1.1 request or response: its start line, its headers and a `Content-Length` body
+/// of at most `max_body` bytes.
+async fn read_message<R>(reader: &mut R, max_body: usize) -> Result<(String, Vec<(String, String)>, Vec<u8>)>
+where
+    R: AsyncBufReadExt + Unpin,
+{
+    let mut start = String::new();
+    read_line(reader, &mut start).await?;
+    let mut headers = vec![];
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if read_line(reader, &mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(eyre!("too many headers: more than {}", MAX_HEADERS));
+        }
+        if let Some((name, value)) = line.trim_end().split_once(':') {
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim().to_string());
+            if name == "content-length" {
+                length = value.parse().map_err(|_| eyre!("invalid content length: {}", value))?;
+            }
+            headers.push((name, value));
+        }
+    }
+    if length > max_body {
+        return Err(eyre!("message body too large: {} bytes", length));
+    }
+    // Let the body grow as it arrives, rather than trusting the declared length up front
+    let mut body = vec![];
+    reader.take(length as u64).read_to_end(&mut body).await?;
+    if body.len() != length {
+        return Err(eyre!("message body truncated: {} of {} bytes", body.len(), length));
+    }
+    Ok((start.trim_end().to_string(), headers, body))
+}
+
+fn respond(status: u16, body: &[u8]) -> Vec<u8> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let mut response = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn route(cache: &Cache, writable: bool, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    // Only the last two segments matter, so the server can sit behind a proxy under any prefix
+    let mut segments = path.rsplit('/');
+    let (Some(name), Some(kind)) = (segments.next(), segments.next()) else {
+        return (404, vec![]);
+    };
+    if !is_digest(name) {
+        return (400, b"invalid key".to_vec());
+    }
+    let result = match (method, kind) {
+        ("GET", "ac") => {
+            return cache
+                .manifest(name)
+                .map_or((404, vec![]), |m| (200, serde_json::to_vec(&m).unwrap_or_default()))
+        }
+        ("GET", "cas") => return cache.blob(name).map_or((404, vec![]), |content| (200, content)),
+        ("PUT", "ac" | "cas") if !writable => return (403, b"cache server is read-only".to_vec()),
+        ("PUT", "ac") => serde_json::from_slice::<Manifest>(body)
+            .map_err(|e| eyre!(e))
+            .and_then(|manifest| {
+                manifest.validate()?;
+                cache.put_manifest(name, &manifest)
+            }),
+        ("PUT", "cas") => cache.put_blob(name, body),
+        ("GET" | "PUT", _) => return (404, vec![]),
+        _ => return (405, vec![]),
+    };
+    match result {
+        Ok(()) => (200, vec![]),
+        Err(err) => (400, err.to_string().into_bytes()),
+    }
+}
+
+async fn handle(stream: TcpStream, cache: &Cache, writable: bool, max_body: usize) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let (request, _, body) = read_message(&mut stream, max_body).await?;
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let (status, body) = route(cache, writable, method, path, &body);
+    stream.get_mut().write_all(&respond(status, &body)).await?;
+    Ok(())
+}
+
+/// Serves the remote cache protocol from `cache` to every connection accepted on `listener`, rejecting
+/// uploads unless `writable` is set. No more than `limits.max_connections` are handled at once.
+///
+/// # Errors
+///
+/// This function will return an error if accepting a connection fails.
+pub async fn serve(listener: TcpListener, cache: Cache, writable: bool, limits: Limits) -> Result<()> {
+    let cache = Arc::new(cache);
+    let connections = Arc::new(Semaphore::new(limits.max_connections.max(1)));
+    loop {
+        let permit = Arc::clone(&connections).acquire_owned().await?;
+        let (stream, peer) = listener.accept().await?;
+        let cache = Arc::clone(&cache);
+        tokio::spawn(async move {
+            if let Err(err) = tokio::time::timeout(TIMEOUT, handle(stream, &cache, writable, limits.max_body))
+                .await
+                .unwrap_or_else(|_| Err(eyre!("request timed out")))
+            {
+                eprintln!("cache-server: {peer}: {err}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+fn cache_server_command(home: &Path) -> Command {
+    Command::new("cache-server")
+        .bin_name("otto cache-server")
+        .about("serve a directory as a remote cache for other otto clients")
+        .arg(
+            Arg::new("listen")
+                .short('l')
+                .long("listen")
+                .value_name("ADDR")
+                .default_value(DEFAULT_LISTEN)
+                .help("address to listen on"),
+        )
+        .arg(
+            Arg::new("dir")
+                .short('d')
+                .long("dir")
+                .value_name("PATH")
+                .default_value(home.join("cache").to_string_lossy().to_string())
+                .help("directory to store cached outputs in"),
+        )
+        .arg(
+            Arg::new("writable")
+                .short('w')
+                .long("writable")
+                .action(ArgAction::SetTrue)
+                .help("accept uploads from clients; the server is read-only otherwise"),
+        )
+        .arg(
+            Arg::new("max-body")
+                .long("max-body")
+                .value_name("BYTES")
+                .default_value(Limits::default().max_body.to_string())
+                .value_parser(value_parser!(usize))
+                .help("largest upload accepted, in bytes"),
+        )
+        .arg(
+            Arg::new("max-connections")
+                .long("max-connections")
+                .value_name("N")
+                .default_value(Limits::default().max_connections.to_string())
+                .value_parser(value_parser!(usize))
+                .help("number of connections handled at the same time"),
+        )
+}
+
+/// Runs `otto cache-server`, serving the directory given by `--dir` (by default the local cache in
+/// `home`) until interrupted.
+///
+/// # Errors
+///
+/// This function will return an error if the arguments are invalid or the address cannot be bound.
+pub async fn run(home: &Path, args: &[String]) -> Result<()> {
+    let matches = cache_server_command(home)
+        .try_get_matches_from(std::iter::once("cache-server".to_string()).chain(args.iter().cloned()))?;
+    let listen = matches.get_one::<String>("listen").expect("listen has a default");
+    let dir = matches.get_one::<String>("dir").expect("dir has a default");
+    let writable = matches.get_flag("writable");
+    let limits = Limits {
+        max_body: *matches.get_one::<usize>("max-body").expect("max-body has a default"),
+        max_connections: *matches.get_one::<usize>("max-connections").expect("max-connections has a default"),
+    };
+    let listener = TcpListener::bind(listen).await?;
+    let mode = if writable { "read-write" } else { "read-only" };
+    println!("serving {} {} on http://{}", dir, mode, listener.local_addr()?);
+    serve(listener, Cache::at(dir.into()), writable, limits).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    async fn start_server(dir: &Path, writable: bool) -> RemoteCache {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/team", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, Cache::at(dir.join("server")), writable, Limits::default()));
+        RemoteCache::parse(&url).unwrap()
+    }
+
+    #[test]
+    fn test_from_api() {
+        assert_eq!(RemoteCache::from_api("1").unwrap(), None);
+        let remote = RemoteCache::from_api("http://cache.local/otto/").unwrap().unwrap();
+        assert_eq!(remote.authority, "cache.local:80");
+        assert_eq!(remote.prefix, "/otto");
+        let remote = RemoteCache::from_api("http://127.0.0.1:8000").unwrap().unwrap();
+        assert_eq!(remote.authority, "127.0.0.1:8000");
+        assert_eq!(remote.prefix, "");
+        assert!(RemoteCache::from_api("https://cache.local").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_and_fetch() {
+        let dir = TempDir::with_dirs("remote", &["work"]);
+        let remote = start_server(&dir, true).await;
+        let key = "a".repeat(64);
+
+        // One client produces an output and uploads it
+        let producer = Cache::at(dir.join("producer"));
+        fs::write(dir.join("work/out.txt"), "generated").unwrap();
+        let manifest = producer
+            .store(&key, &["out.txt".to_string()], &dir.join("work"))
+            .unwrap();
+        remote.upload(&key, &manifest, &producer).await.unwrap();
+
+        // Another client with an empty cache fetches and restores it
+        let consumer = Cache::at(dir.join("consumer"));
+        assert_eq!(remote.fetch(&"b".repeat(64), &consumer).await.unwrap(), None);
+        let fetched = remote.fetch(&key, &consumer).await.unwrap().unwrap();
+        assert_eq!(fetched, manifest);
+        fs::remove_file(dir.join("work/out.txt")).unwrap();
+        consumer
+            .restore(&consumer.lookup(&key).unwrap(), &dir.join("work"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("work/out.txt")).unwrap(), "generated");
+    }
+
+    #[tokio::test]
+    async fn test_server_rejects_bad_requests() {
+        let dir = TempDir::with_dirs("remote-reject", &["work"]);
+        let remote = start_server(&dir, true).await;
+
+        assert_eq!(
+            remote.request("GET", "/cas/../../etc/passwd", &[]).await.unwrap().0,
+            400
+        );
+        assert_eq!(
+            remote
+                .request("PUT", &format!("/cas/{}", "c".repeat(64)), b"not c")
+                .await
+                .unwrap()
+                .0,
+            400
+        );
+        assert_eq!(
+            remote
+                .request("DELETE", &format!("/ac/{}", "c".repeat(64)), &[])
+                .await
+                .unwrap()
+                .0,
+            405
+        );
+        assert_eq!(
+            remote
+                .request("GET", &format!("/ac/{}", "c".repeat(64)), &[])
+                .await
+                .unwrap()
+                .0,
+            404
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_message_is_bounded() {
+        let read = |message: Vec<u8>, max_body: usize| async move { read_message(&mut &message[..], max_body).await };
+        let (start, headers, body) = read(b"PUT /cas/x HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi".to_vec(), 2).await.unwrap();
+        assert_eq!((start.as_str(), headers.len(), body.as_slice()), ("PUT /cas/x HTTP/1.1", 1, &b"hi"[..]));
+
+        let err = read(b"PUT /cas/x HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc".to_vec(), 2).await.unwrap_err();
+        assert!(err.to_string().contains("too large"), "{err}");
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE)).into_bytes();
+        assert!(read(long, 2).await.unwrap_err().to_string().contains("line too long"));
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_LINE)).into_bytes();
+        assert!(read(long, 2).await.unwrap_err().to_string().contains("line too long"));
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS + 1)).into_bytes();
+        assert!(read(many, 2).await.unwrap_err().to_string().contains("too many headers"));
+    }
+
+    #[tokio::test]
+    async fn test_server_limits_concurrent_connections() {
+        let dir = TempDir::with_dirs("remote-connections", &["work"]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote = RemoteCache::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let limits = Limits {
+            max_connections: 1,
+            ..Limits::default()
+        };
+        tokio::spawn(serve(listener, Cache::at(dir.join("server")), false, limits));
+
+        // An idle connection holds the only slot until it goes away
+        let idle = TcpStream::connect(&remote.authority).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let key = "e".repeat(64);
+        let waiting = tokio::time::timeout(Duration::from_millis(200), remote.get(&format!("/ac/{key}"))).await;
+        assert!(waiting.is_err(), "the second connection waits");
+        drop(idle);
+        assert_eq!(remote.get(&format!("/ac/{key}")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_server_is_read_only_by_default() {
+        let dir = TempDir::with_dirs("remote-read-only", &["work"]);
+        let remote = start_server(&dir, false).await;
+        let key = "d".repeat(64);
+        let producer = Cache::at(dir.join("producer"));
+        fs::write(dir.join("work/out.txt"), "generated").unwrap();
+        let manifest = producer
+            .store(&key, &["out.txt".to_string()], &dir.join("work"))
+            .unwrap();
+
+        assert!(remote.upload(&key, &manifest, &producer).await.is_err());
+        assert_eq!(
+            remote
+                .request("PUT", &format!("/ac/{key}"), b"{\"files\":[]}")
+                .await
+                .unwrap()
+                .0,
+            403
+        );
+        assert_eq!(
+            remote.fetch(&key, &Cache::at(dir.join("consumer"))).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malicious_manifests_are_rejected() {
+        let dir = TempDir::with_dirs("remote-malicious", &["work"]);
+        let remote = start_server(&dir, true).await;
+        let key = "e".repeat(64);
+        let blob = "f".repeat(64);
+        let malicious = format!(r#"{{"files":[{{"path":"../../escaped","blob":"{blob}","mode":420}}]}}"#);
+
+        // The server refuses to store it...
+        assert_eq!(
+            remote
+                .request("PUT", &format!("/ac/{key}"), malicious.as_bytes())
+                .await
+                .unwrap()
+                .0,
+            400
+        );
+
+        // ...and a client refuses it from a server that did
+        let server = Cache::at(dir.join("server"));
+        fs::create_dir_all(dir.join("server/ac")).unwrap();
+        fs::write(dir.join(format!("server/ac/{key}.json")), &malicious).unwrap();
+        assert!(server.manifest(&key).is_some());
+        let consumer = Cache::at(dir.join("consumer"));
+        assert!(remote.fetch(&key, &consumer).await.is_err());
+        assert!(consumer.manifest(&key).is_none());
+    }
+}
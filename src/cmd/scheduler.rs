@@ -1,19 +1,34 @@
 //#![allow(unused_imports, unused_variables, unused_attributes, unused_mut, dead_code)]
 
 use eyre::{eyre, Result};
-use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::collections::{HashSet, HashMap, VecDeque};
-use std::str;
+use std::process::{ExitStatus, Stdio};
+use std::env;
 use std::fs;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
 use once_cell::sync::Lazy;
 use expanduser::expanduser;
+use daggy::petgraph::algo::toposort;
+use daggy::{NodeIndex, Walker};
+use tokio::fs::File;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
 
-use crate::cli::parse::{TaskSpec, DAG};
-use crate::cfg::param::Value;
+use crate::cli::parse::{Edge, TaskSpec, DAG};
 use crate::cfg::otto::Otto;
+use crate::cmd::cache::{Cache, Manifest};
+use crate::cmd::argv;
+use crate::cmd::env::encode_all;
+use crate::cmd::error::{RunSummary, SchedulerError, TaskStatus};
+use crate::cmd::output::{stream_lines, Prefix, Sink, Stream};
+use crate::cmd::process::{self, ProcessGroup};
+use crate::cmd::fingerprint;
+use crate::cmd::record::{RunRecord, TaskRecord};
+use crate::cmd::remote::RemoteCache;
+use crate::cmd::script;
 
 static TIMESTAMP: Lazy<u64> = Lazy::new(|| {
     SystemTime::now()
@@ -22,11 +37,40 @@ static TIMESTAMP: Lazy<u64> = Lazy::new(|| {
         .as_secs()
 });
 
+/// Everything shared by the tasks of a run.
+struct RunContext {
+    run_dir: PathBuf,
+    store_dir: PathBuf,
+    cache: Cache,
+    remote: Option<RemoteCache>,
+    /// Where otto was invoked from, which is where tasks without a directory of their own run
+    invocation_dir: PathBuf,
+    /// The `OTTO_*` variables that are the same for every task of the run
+    env: HashMap<String, String>,
+    /// The signal the run was interrupted with, once it has been
+    interrupt: watch::Receiver<Option<libc::c_int>>,
+    /// Set once a failed task cancels the run, which kills the running tasks without a grace period
+    abort: watch::Receiver<bool>,
+    grace_period: Duration,
+    /// What the variables of task parameters start with
+    env_prefix: String,
+}
+
+/// Completes after `timeout`, or never if there is none.
+async fn expire(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
 pub struct Scheduler {
     pub otto: Otto,
     pub tasks: DAG<TaskSpec>,
     pub hash: String,
     pub timestamp: u64,
+    /// The Ottofile the tasks were loaded from, if any.
+    pub ottofile: Option<PathBuf>,
 }
 
 impl Scheduler {
@@ -37,136 +81,511 @@ impl Scheduler {
             tasks,
             hash,
             timestamp: *TIMESTAMP,
+            ottofile: None,
         }
     }
 
     /// Run the scheduler asynchronously.
     ///
-    /// # Errors
+    /// Tasks are dispatched from a ready queue: each selected node of the DAG tracks how many of
+    /// its selected dependencies are still outstanding, and a task is only spawned once that count
+    /// reaches zero. Workers never poll; the scheduler awaits the next finished task and releases
+    /// its dependents.
     ///
-    /// This function will return an error if it fails to lock a shared resource,
-    /// fails to create a directory, fails to write an action to a file, or fails to execute a command.
+    /// When a task fails, its dependents are skipped. By default the run is then cancelled and
+    /// in-flight tasks are killed; with `keep_going` every task not downstream of a failure still runs.
     ///
-    /// # Panics
+    /// SIGINT and SIGTERM cancel the run: no further tasks are started, and the signal is forwarded
+    /// to the process group of every running task, which is killed if it has not exited after the
+    /// grace period. A second signal kills the running tasks immediately.
     ///
-    /// This function can panic if a task is missing from the task queue. This should never occur under normal circumstances.
+    /// # Errors
+    ///
+    /// This function will return an error if it fails to create a directory, a
+    /// [`SchedulerError::RunFailed`] listing the failed, skipped, cancelled and succeeded tasks
+    /// if not every selected task succeeded, or a [`SchedulerError::Cancelled`] if the run was interrupted.
     pub async fn run_async(&self) -> Result<()> {
+        let (sender, interrupt) = watch::channel(None);
+        let forwarder = process::forward_signals(sender)?;
+        let result = self.run_until_interrupted(interrupt).await;
+        forwarder.abort();
+        result
+    }
+
+    async fn run_until_interrupted(&self, mut interrupt: watch::Receiver<Option<libc::c_int>>) -> Result<()> {
+        let started = SystemTime::now();
+
         // Find the set of tasks to execute
-        let tasks_to_execute = self.get_tasks_to_execute()?;
-        let num_tasks = tasks_to_execute.len();
+        let selected = self.selected()?;
 
-        let completed_tasks: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
-        let task_queue: Arc<Mutex<VecDeque<TaskSpec>>> = Arc::new(Mutex::new(VecDeque::new()));
+        // Count the outstanding dependencies of every selected task
+        let mut indegrees: HashMap<NodeIndex, usize> = selected
+            .iter()
+            .map(|index| {
+                let count = self
+                    .tasks
+                    .parents(*index)
+                    .iter(&self.tasks)
+                    .filter(|(_, parent)| selected.contains(parent))
+                    .count();
+                (*index, count)
+            })
+            .collect();
 
-        let path = Arc::new(self.create_dir()?);  // wrap it in an Arc
-        // Populate the task queue
-        for node in self.tasks.raw_nodes() {
-            let task = node.weight.clone();
-            if tasks_to_execute.contains(&task.name) {
-                task_queue.lock().unwrap().push_back(task);
-            }
-        }
+        // Seed the ready queue with the tasks that have no dependencies, in DAG order
+        let mut ready: VecDeque<NodeIndex> = self
+            .tasks
+            .graph()
+            .node_indices()
+            .filter(|index| indegrees.get(index) == Some(&0))
+            .collect();
 
-        let mut handles = Vec::new();
+        // Fingerprint every selected task up front, then again as it is dispatched, once its
+        // dependencies have run and may have changed its inputs
+        let invocation_dir = self.invocation_dir()?;
+        let mut dag = self.tasks.clone();
+        fingerprint::fingerprint_dag(&mut dag, &invocation_dir, &selected)?;
 
-        for _ in 0..self.otto.jobs {
-            let completed_tasks = completed_tasks.clone();
-            let task_queue = task_queue.clone();
-            let path = Arc::clone(&path);  // clone the Arc, not the PathBuf
+        let run_dir = self.create_dir()?;
+        let env = self.run_env(&run_dir, &invocation_dir)?;
+        let (aborter, abort) = watch::channel(false);
+        let context = Arc::new(RunContext {
+            run_dir,
+            store_dir: self.store_dir()?,
+            cache: Cache::new(&self.home_dir()?),
+            remote: RemoteCache::from_api(&self.otto.api)?,
+            invocation_dir: invocation_dir.clone(),
+            env,
+            interrupt: interrupt.clone(),
+            abort,
+            grace_period: self.otto.grace_period,
+            env_prefix: self.otto.env_prefix.clone(),
+        });
+        let jobs = self.otto.jobs.max(1);
+        let width = selected.iter().map(|index| self.tasks[*index].name.len()).max().unwrap_or(0);
+        let mut running: JoinSet<(NodeIndex, u32, Result<TaskStatus>)> = JoinSet::new();
+        let mut in_flight: HashSet<NodeIndex> = HashSet::new();
+        let mut statuses: HashMap<NodeIndex, TaskStatus> = HashMap::new();
+        let mut attempts: HashMap<String, u32> = HashMap::new();
+        let mut cancelled = false;
+        let mut signal = None;
 
-            let handle = tokio::spawn(async move {
-                loop {
-                    let task = {
-                        let mut task_queue = task_queue.lock().unwrap();
-                        if task_queue.is_empty() {
-                            break;
-                        }
-                        task_queue.pop_front().unwrap()
+        loop {
+            // Dispatch as many ready tasks as there are free job slots
+            while !cancelled && running.len() < jobs {
+                let Some(index) = ready.pop_front() else { break };
+                let fingerprinted = fingerprint::fingerprint(&dag[index], &invocation_dir, &fingerprint::parent_fingerprints(&dag, index))
+                    .map(|fingerprint| dag[index].fingerprint = fingerprint);
+                let task = dag[index].clone();
+                let context = Arc::clone(&context);
+                let prefix = Prefix::new(&task.name, width, index.index());
+                let sink = Sink::new(prefix, self.otto.output, task.show);
+                in_flight.insert(index);
+                running.spawn(async move {
+                    let mut attempts = 0;
+                    let result = match fingerprinted {
+                        Ok(()) => Self::run_task(task, &context, sink, &mut attempts).await,
+                        Err(err) => Err(err),
                     };
+                    (index, attempts, result)
+                });
+            }
 
-                    let env = Self::setup_env(&task.clone());
+            // Wait for the next task to finish; nothing running means nothing left to do
+            let joined = tokio::select! {
+                joined = running.join_next() => joined,
+                Ok(()) = interrupt.changed() => {
+                    // The running tasks see the signal too and stop themselves; on a second one
+                    // they are killed without waiting for the grace period
+                    if signal.is_some() {
+                        running.abort_all();
+                    }
+                    signal = *interrupt.borrow_and_update();
+                    cancelled = true;
+                    continue;
+                }
+            };
+            let Some(joined) = joined else { break };
+            let (index, attempted, result) = match joined {
+                Ok(joined) => joined,
+                Err(err) if err.is_cancelled() => continue,
+                Err(err) => return Err(err.into()),
+            };
+            in_flight.remove(&index);
+            if attempted > 0 {
+                attempts.insert(self.tasks[index].name.clone(), attempted);
+            }
+            match result {
+                Err(err)
+                    if matches!(err.downcast_ref(), Some(SchedulerError::Interrupted(_) | SchedulerError::Aborted(_))) =>
+                {
+                    statuses.insert(index, TaskStatus::Cancelled);
+                }
+                Ok(status) => {
+                    statuses.insert(index, status);
+                    self.release(index, None, &mut indegrees, &mut statuses, &mut ready);
+                }
+                Err(err) => {
+                    let name = &self.tasks[index].name;
+                    eprintln!("Error executing task {name}: {err}");
+                    statuses.insert(index, TaskStatus::Failed(err.to_string()));
 
-                    let result = async {
-                        let mut all_deps_completed = false;
+                    // Nothing that requires a failure can run; in keep-going mode everything else still does
+                    let reason = format!("{name} failed");
+                    self.release(index, Some(&reason), &mut indegrees, &mut statuses, &mut ready);
+                    if !self.otto.keep_going && !cancelled {
+                        // The running tasks are killed, but still record themselves and show their output
+                        cancelled = true;
+                        aborter.send_replace(true);
+                    }
+                }
+            }
+        }
 
-                        while !all_deps_completed {
-                            {
-                                let completed_tasks = completed_tasks.lock().unwrap();
-                                all_deps_completed = task.deps.iter().all(|dep| completed_tasks.contains(dep));
-                            }
-                            if !all_deps_completed {
-                                continue;
-                            }
+        // Whatever was still running when the run was cancelled was killed, whatever never started was skipped
+        let summary = RunSummary {
+            tasks: self
+                .tasks
+                .graph()
+                .node_indices()
+                .filter(|index| selected.contains(index))
+                .map(|index| {
+                    let status = statuses.remove(&index).unwrap_or_else(|| {
+                        if in_flight.contains(&index) {
+                            TaskStatus::Cancelled
+                        } else {
+                            TaskStatus::Skipped("the run was cancelled".to_string())
                         }
-                        let mut path = path.as_path().to_path_buf();  // clone the PathBuf, not the Arc
-                        path.push(&task.name);
+                    });
+                    (self.tasks[index].name.clone(), status)
+                })
+                .collect(),
+            attempts,
+        };
+        let signal = signal.map(process::signal_name);
+        RunRecord::new(&summary, signal, started, SystemTime::now()).write(&context.run_dir)?;
+        if signal.is_some() {
+            return Err(SchedulerError::Cancelled(summary).into());
+        }
+        if !summary.is_success() {
+            return Err(SchedulerError::RunFailed(summary).into());
+        }
 
-                        // Write the action to a file
-                        tokio::fs::write(&path, &task.action).await.map_err(|e| eyre!("Failed to write action to file: {}", e))?;
+        Ok(())
+    }
 
-                        // All dependencies are completed, now run the task
-                        let output = tokio::task::spawn_blocking(move || {
-                            Command::new("sh")
-                                .envs(&env)
-                                .arg(path) // execute the script
-                                .output()
-                        }).await
-                        .map_err(|e| eyre!("Failed to execute command: {}", e))?;
+    /// Prints what every selected task would run, dependencies first, without running anything.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a requested task does not exist, the DAG has a cycle
+    /// or a `cmd` refers to an unknown parameter.
+    pub fn dry_run(&self) -> Result<()> {
+        let tasks_to_execute = self.get_tasks_to_execute()?;
+        let order = toposort(self.tasks.graph(), None).map_err(|_| eyre!("Circular dependency detected"))?;
+        for index in order {
+            if tasks_to_execute.contains(&self.tasks[index].name) {
+                println!("{}", Self::describe(&self.tasks[index])?);
+            }
+        }
+        Ok(())
+    }
 
-                        let output = output.map_err(|e| eyre!("Failed to execute command: {}", e))?;
+    /// The command line of a `cmd` task, exactly as it would be executed, or the shell and the
+    /// indented script of an action.
+    fn describe(task: &TaskSpec) -> Result<String> {
+        if !task.cmd.is_empty() {
+            return Ok(format!("{}: {}", task.name, argv::display(&task.argv()?)));
+        }
+        let mut description = match &task.shell {
+            Some(shell) => format!("{}: {}", task.name, shell),
+            None => format!("{}:", task.name),
+        };
+        if !task.args.is_empty() {
+            description.push_str(" -- ");
+            description.push_str(&argv::display(&task.args));
+        }
+        for line in task.action.lines() {
+            description.push_str("\n    ");
+            description.push_str(line);
+        }
+        Ok(description)
+    }
 
-                        let stdout = str::from_utf8(&output.stdout)
-                            .map_err(|e| eyre!("Failed to parse stdout as UTF-8: {}", e))?;
-                        println!("{stdout}");
+    /// Lets the selected tasks waiting on the finished task at `index` go ahead, queueing those
+    /// that wait on nothing else. If it failed for `reason`, the tasks that require it are skipped
+    /// instead, and so on down the DAG; tasks only ordered after it still run.
+    fn release(
+        &self,
+        index: NodeIndex,
+        reason: Option<&str>,
+        indegrees: &mut HashMap<NodeIndex, usize>,
+        statuses: &mut HashMap<NodeIndex, TaskStatus>,
+        ready: &mut VecDeque<NodeIndex>,
+    ) {
+        let mut finished = vec![index];
+        while let Some(parent) = finished.pop() {
+            for (edge, child) in self.tasks.children(parent).iter(&self.tasks) {
+                let Some(count) = indegrees.get_mut(&child) else { continue };
+                if statuses.contains_key(&child) {
+                    continue;
+                }
+                if let Some(reason) = reason.filter(|_| self.tasks[edge] == Edge::Requires) {
+                    statuses.insert(child, TaskStatus::Skipped(reason.to_string()));
+                    finished.push(child);
+                } else {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.push_back(child);
+                    }
+                }
+            }
+        }
+    }
 
-                        if !output.status.success() {
-                            return Err(eyre!("Task {} failed with exit code {:?}", task.name, output.status.code()));
-                        }
+    /// Runs one task unless it is up to date or its outputs can be restored from the cache. A failing
+    /// task is retried up to `retries` times, waiting `retry_delay` before the first retry and twice
+    /// as long before each one after that; `attempts` counts how often the action was started.
+    async fn run_task(task: TaskSpec, context: &RunContext, sink: Sink, attempts: &mut u32) -> Result<TaskStatus> {
+        // Tasks run in, and resolve their inputs and outputs against, their own directory
+        let dir = task.dir.clone().unwrap_or_else(|| context.invocation_dir.clone());
+        if !dir.is_dir() {
+            return Err(eyre!("Task {} cannot run in {}: no such directory", task.name, dir.display()));
+        }
+        if fingerprint::is_up_to_date(&task, &dir, &context.store_dir, &task.fingerprint) {
+            return Ok(TaskStatus::UpToDate);
+        }
+        fingerprint::invalidate(&task, &context.store_dir)?;
 
-                        // Mark the task as completed
-                        completed_tasks.lock().unwrap().insert(task.name.clone());
+        // Outputs produced before by an identical task are restored instead of rebuilt
+        let cacheable = task.cache && !task.outputs.is_empty();
+        if cacheable {
+            if let Some(manifest) = Self::lookup_cache(&task, context).await {
+                context.cache.restore(&manifest, &dir)?;
+                fingerprint::store(&task, &context.store_dir, &task.fingerprint)?;
+                return Ok(TaskStatus::Cached);
+            }
+        }
 
-                        Ok(())
-                    };
+        let env = Self::setup_env(&task, context)?;
 
-                    if let Err(err) = result.await {
-                        eprintln!("Error executing task {}: {}", task.name, err);
-                    }
+        // Keep a raw copy of each stream in the run directory, across all attempts
+        let mut stdout_log = File::create(context.run_dir.join(format!("{}.stdout", task.file_stem()))).await?;
+        let mut stderr_log = File::create(context.run_dir.join(format!("{}.stderr", task.file_stem()))).await?;
+
+        let started = SystemTime::now();
+        let mut delay = task.retry_delay;
+        let result = loop {
+            *attempts += 1;
+            let result = Self::execute(&task, &env, &dir, context, &sink, &mut stdout_log, &mut stderr_log).await;
+            let stopped = context.interrupt.borrow().is_some() || *context.abort.borrow();
+            if matches!(&result, Ok(status) if status.success()) || *attempts > task.retries || stopped {
+                break result;
+            }
+            let reason = result.as_ref().map_or_else(ToString::to_string, ToString::to_string);
+            eprintln!(
+                "Task {} failed on attempt {} of {} ({}); retrying in {:?}",
+                task.name,
+                attempts,
+                task.retries + 1,
+                reason,
+                delay
+            );
+            // An interrupt or abort while waiting ends the task like one while it runs, output and record included
+            let mut interrupt = context.interrupt.clone();
+            let mut abort = context.abort.clone();
+            let stopped = tokio::select! {
+                () = tokio::time::sleep(delay) => None,
+                _ = interrupt.changed() => Some(SchedulerError::Interrupted(task.name.clone())),
+                _ = abort.changed() => Some(SchedulerError::Aborted(task.name.clone())),
+            };
+            if let Some(err) = stopped {
+                break Err(err.into());
+            }
+            delay = delay.saturating_mul(2);
+        };
+        let success = matches!(&result, Ok(status) if status.success());
+        sink.finish(success).map_err(|e| eyre!("Failed to print output: {}", e))?;
+        TaskRecord::new(&task, env, started, SystemTime::now(), *attempts, &result).write(&context.run_dir)?;
+
+        let status = result?;
+        if !status.success() {
+            return Err(eyre!("Task {} failed with exit code {:?}", task.name, status.code()));
+        }
+        if cacheable {
+            let manifest = context.cache.store(&task.fingerprint, &task.outputs, &dir)?;
+            if let Some(remote) = &context.remote {
+                if let Err(err) = remote.upload(&task.fingerprint, &manifest, &context.cache).await {
+                    eprintln!("Failed to upload {} to the remote cache: {}", task.name, err);
                 }
-            });
+            }
+        }
+        fingerprint::store(&task, &context.store_dir, &task.fingerprint)?;
+
+        Ok(TaskStatus::Succeeded)
+    }
 
-            handles.push(handle);
+    /// Looks the task's outputs up in the local cache, then in the remote cache if one is configured.
+    /// The remote cache is best effort: if it cannot be reached the task simply runs.
+    async fn lookup_cache(task: &TaskSpec, context: &RunContext) -> Option<Manifest> {
+        if let Some(manifest) = context.cache.lookup(&task.fingerprint) {
+            return Some(manifest);
         }
+        let remote = context.remote.as_ref()?;
+        match remote.fetch(&task.fingerprint, &context.cache).await {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                eprintln!("Failed to fetch {} from the remote cache: {}", task.name, err);
+                None
+            }
+        }
+    }
+
+    async fn execute(
+        task: &TaskSpec,
+        env: &HashMap<String, String>,
+        dir: &Path,
+        context: &RunContext,
+        sink: &Sink,
+        stdout_log: &mut File,
+        stderr_log: &mut File,
+    ) -> Result<ExitStatus> {
+        let mut command = if task.cmd.is_empty() {
+            // Arguments passed through to the task are the script's "$@"
+            let mut command =
+                script::prepare(&task.file_stem(), &task.action, task.shell.as_deref(), &context.run_dir).await?;
+            command.args(&task.args);
+            command
+        } else {
+            // Arguments are passed to the program as they are, without a shell in between
+            let argv = task.argv()?;
+            let (program, args) = argv.split_first().ok_or_else(|| eyre!("Task {} has an empty cmd", task.name))?;
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        };
+
+        // All dependencies are completed, now run the task in a process group of its own
+        command
+            .current_dir(dir)
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true);
+        let mut child = script::spawn(&mut command).await.map_err(|e| eyre!("Failed to execute command: {}", e))?;
+        let mut group = ProcessGroup::of(&child);
+
+        // Stream stdout and stderr line by line while the task runs
+        let stdout = child.stdout.take().ok_or_else(|| eyre!("Failed to capture stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| eyre!("Failed to capture stderr"))?;
+        let streams = async {
+            tokio::join!(
+                stream_lines(stdout, sink, Stream::Stdout, stdout_log),
+                stream_lines(stderr, sink, Stream::Stderr, stderr_log),
+                child.wait(),
+            )
+        };
+        tokio::pin!(streams);
 
-        // Wait for all workers to complete
-        for handle in handles {
-            handle.await?;
+        // A task that runs out of time, or is aborted because another one failed, is killed with
+        // everything it started. An interrupted task is passed the signal and gets the grace period
+        // to exit before it is killed. Either way its output up to that point is still collected
+        let mut interrupt = context.interrupt.clone();
+        let mut abort = context.abort.clone();
+        let mut timed_out = false;
+        let mut interrupted = false;
+        let mut aborted = false;
+        let (stdout, stderr, status) = tokio::select! {
+            finished = &mut streams => finished,
+            () = expire(task.timeout) => {
+                timed_out = true;
+                if let Some(group) = &group {
+                    group.kill();
+                }
+                streams.await
+            }
+            Ok(()) = abort.changed() => {
+                aborted = true;
+                if let Some(group) = &group {
+                    group.kill();
+                }
+                streams.await
+            }
+            Ok(()) = interrupt.changed() => {
+                interrupted = true;
+                let signal = interrupt.borrow().unwrap_or(libc::SIGTERM);
+                if let Some(group) = &group {
+                    group.signal(signal).ok();
+                }
+                match tokio::time::timeout(context.grace_period, &mut streams).await {
+                    Ok(finished) => finished,
+                    Err(_) => {
+                        if let Some(group) = &group {
+                            group.kill();
+                        }
+                        streams.await
+                    }
+                }
+            }
+        };
+        if let Some(group) = &mut group {
+            group.disarm();
+        }
+        stdout.map_err(|e| eyre!("Failed to stream stdout: {}", e))?;
+        stderr.map_err(|e| eyre!("Failed to stream stderr: {}", e))?;
+        let status = status.map_err(|e| eyre!("Failed to execute command: {}", e))?;
+        if timed_out {
+            return Err(eyre!("Task {} timed out after {:?}", task.name, task.timeout.unwrap_or_default()));
+        }
+        if interrupted {
+            return Err(SchedulerError::Interrupted(task.name.clone()).into());
+        }
+        if aborted {
+            return Err(SchedulerError::Aborted(task.name.clone()).into());
         }
 
-        let completed_tasks_count = completed_tasks.lock().unwrap().len();
-        if completed_tasks_count != num_tasks{
-            return Err(eyre!("Not all tasks were completed. Completed: {}, Expected: {}", completed_tasks_count, num_tasks));
+        Ok(status)
+    }
+
+    /// The expanded Otto home directory, made absolute so it stays valid inside task directories.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the home directory cannot be expanded.
+    pub fn home_dir(&self) -> Result<PathBuf> {
+        let canonical = expanduser(&self.otto.home)
+            .map_err(|e| eyre!("Failed to expand home directory: {}", e))?;
+        Ok(std::path::absolute(canonical)?)
+    }
+
+    /// Where otto was invoked from: `--invocation-dir` if given, made absolute, otherwise the current directory.
+    fn invocation_dir(&self) -> Result<PathBuf> {
+        match &self.otto.invocation_dir {
+            Some(dir) => Ok(std::path::absolute(dir)?),
+            None => Ok(env::current_dir()?),
         }
+    }
 
-        Ok(())
+    /// The hidden `.<hash>` directory belonging to the Ottofile, which outlives individual runs.
+    fn store_dir(&self) -> Result<PathBuf> {
+        Ok(self.home_dir()?.join(format!(".{}", &self.hash)))
     }
 
     fn create_dir(&self) -> Result<PathBuf> {
         // Construct the path
-        let canonical = expanduser(&self.otto.home)
-            .map_err(|e| eyre!("Failed to expand home directory: {}", e))?;
-        let home_dir = PathBuf::from(&canonical);
+        let home_dir = self.home_dir()?;
 
         // Create the hidden directory if it doesn't already exist
-        let hidden_hash_dir = format!(".{}", &self.hash);
-        let hidden_dir_path = home_dir.join(hidden_hash_dir);
+        let hidden_dir_path = self.store_dir()?;
         if !hidden_dir_path.exists() {
-            fs::create_dir(&hidden_dir_path)?;
+            fs::create_dir_all(&hidden_dir_path)?;
         }
 
         // Create the timestamp directory
         let timestamp_dir_path = home_dir.join(self.timestamp.to_string());
-        fs::create_dir(&timestamp_dir_path)?;
+        fs::create_dir_all(&timestamp_dir_path)?;
 
         // Create a symlink from the <first-12-chars-of-hex-hash> -> .<64-char-hex-hash>
         let symlink_name = &self.hash[..12];
@@ -186,20 +605,65 @@ impl Scheduler {
         Ok(timestamp_dir_path)
     }
 
+    /// The tasks named on the command line or in the Ottofile, where "*" selects every task in the DAG.
+    fn requested_tasks(&self) -> Vec<String> {
+        if self.otto.tasks.iter().any(|task| task == "*") {
+            self.tasks.raw_nodes().iter().map(|node| node.weight.name.clone()).collect()
+        } else {
+            self.otto.tasks.clone()
+        }
+    }
+
+    /// The nodes of the requested tasks and of every task they depend on.
+    fn selected(&self) -> Result<HashSet<NodeIndex>> {
+        let tasks_to_execute = self.get_tasks_to_execute()?;
+        Ok(self
+            .tasks
+            .graph()
+            .node_indices()
+            .filter(|index| tasks_to_execute.contains(&self.tasks[*index].name))
+            .collect())
+    }
+
+    /// The fingerprint of every requested task, computed from the current state of its inputs.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a requested task does not exist or a fingerprint cannot be computed.
+    pub fn fingerprints(&self) -> Result<Vec<(String, String)>> {
+        let mut dag = self.tasks.clone();
+        fingerprint::fingerprint_dag(&mut dag, &self.invocation_dir()?, &self.selected()?)?;
+        self.requested_tasks()
+            .into_iter()
+            .map(|name| {
+                let node = dag
+                    .raw_nodes()
+                    .iter()
+                    .find(|node| node.weight.name == name)
+                    .ok_or_else(|| eyre!("Task not found: {}", name))?;
+                Ok((name, node.weight.fingerprint.clone()))
+            })
+            .collect()
+    }
+
     pub fn get_tasks_to_execute(&self) -> Result<HashSet<String>> {
         let mut tasks_to_execute = HashSet::new();
         let mut visited_tasks = HashSet::new();
         let mut path = HashSet::new();
 
-        for task in &self.otto.tasks {
+        for task in &self.requested_tasks() {
+            if !self.tasks.raw_nodes().iter().any(|node| node.weight.name == *task) {
+                return Err(eyre!("Task not found: {}", task));
+            }
+            // A task already reached as a dependency of an earlier one needs no second visit;
+            // cycles are detected by the path tracking in add_dependencies.
             if visited_tasks.contains(task) {
-                return Err(eyre!("Circular dependency detected for task: {}", task));
-            } else {
-                visited_tasks.insert(task.clone());
-                path.insert(task.clone());
-                self.add_dependencies(task, &mut tasks_to_execute, &mut visited_tasks, &mut path)?;
-                path.remove(task);
+                continue;
             }
+            visited_tasks.insert(task.clone());
+            path.insert(task.clone());
+            self.add_dependencies(task, &mut tasks_to_execute, &mut visited_tasks, &mut path)?;
+            path.remove(task);
         }
 
         Ok(tasks_to_execute)
@@ -232,18 +696,602 @@ impl Scheduler {
         Ok(())
     }
 
-    fn setup_env(task: &TaskSpec) -> HashMap<String, String> {
+    /// The variables otto sets for every task of a run:
+    ///
+    /// - `OTTO_RUN_ID`: the id of the run, which names its run directory
+    /// - `OTTO_RUN_DIR`: the run directory, holding the logs and records of every task
+    /// - `OTTO_HOME`: the Otto home directory
+    /// - `OTTO_OTTOFILE`: the absolute path of the Ottofile, empty if there is none
+    /// - `OTTO_PROJECT_DIR`: the directory of the Ottofile, or the invocation directory without one
+    /// - `OTTO_INVOCATION_DIR`: the directory otto was invoked from, or the one given with `--invocation-dir`
+    /// - `OTTO_JOBS`: the number of tasks run in parallel
+    /// - `OTTO_CONFIG_HASH`: the hash of the Ottofile
+    fn run_env(&self, run_dir: &Path, invocation_dir: &Path) -> Result<HashMap<String, String>> {
+        let path = |path: &Path| path.to_string_lossy().to_string();
+        let project_dir = self.ottofile.as_deref().and_then(Path::parent).unwrap_or(invocation_dir);
+        Ok(HashMap::from([
+            ("OTTO_RUN_ID".to_string(), self.timestamp.to_string()),
+            ("OTTO_RUN_DIR".to_string(), path(run_dir)),
+            ("OTTO_HOME".to_string(), path(&self.home_dir()?)),
+            ("OTTO_OTTOFILE".to_string(), self.ottofile.as_deref().map(path).unwrap_or_default()),
+            ("OTTO_PROJECT_DIR".to_string(), path(project_dir)),
+            ("OTTO_INVOCATION_DIR".to_string(), path(invocation_dir)),
+            ("OTTO_JOBS".to_string(), self.otto.jobs.to_string()),
+            ("OTTO_CONFIG_HASH".to_string(), self.hash.clone()),
+        ]))
+    }
+
+    /// The environment of a task: its parameter values, with lists and dicts encoded as
+    /// [`encode`](crate::cmd::env::encode) describes, then the variables of the run, `OTTO_TASK`
+    /// and `OTTO_TASK_HASH`, its name and fingerprint, and `OTTO_ARGS`, the arguments passed
+    /// through to it, quoted for a shell. Otto's own variables win.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if two parameter values would set the same variable.
+    fn setup_env(task: &TaskSpec, context: &RunContext) -> Result<HashMap<String, String>> {
         let mut env: HashMap<String, String> = HashMap::new();
         for (k, v) in &task.envs {
             env.insert(k.into(), v.into());
         }
-        for (k, v) in &task.values {
-            if let Value::Item(val) = v {
-                env.insert(k.into(), val.into());
+        env.extend(encode_all(&context.env_prefix, &task.values).map_err(|e| eyre!("Task {}: {}", task.name, e))?);
+        env.extend(context.env.clone());
+        env.insert("OTTO_TASK".into(), task.name.clone());
+        env.insert("OTTO_TASK_HASH".into(), task.fingerprint.clone());
+        env.insert("OTTO_ARGS".into(), argv::display(&task.args));
+        Ok(env)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::otto::OutputMode;
+    use crate::cfg::param::Value;
+    use crate::cli::parse::DEFAULT_HASH;
+    use crate::cmd::remote::Limits;
+    use crate::testing::TempDir;
+
+    fn generate_test_otto(home: &Path, tasks: &[&str]) -> Otto {
+        Otto {
+            home: home.to_string_lossy().to_string(),
+            jobs: 4,
+            tasks: tasks.iter().map(|t| (*t).to_string()).collect(),
+            ..Otto::default()
+        }
+    }
+
+    fn generate_test_dag(specs: &[(&str, &[&str], String)]) -> DAG<TaskSpec> {
+        let mut dag: DAG<TaskSpec> = DAG::new();
+        let mut indices = HashMap::new();
+        for (name, deps, action) in specs {
+            let deps = deps.iter().map(|d| (*d).to_string()).collect();
+            let spec = TaskSpec::new((*name).to_string(), deps, HashMap::new(), HashMap::new(), action.clone());
+            indices.insert(*name, dag.add_node(spec));
+        }
+        for (name, deps, _) in specs {
+            for dep in *deps {
+                dag.add_edge(indices[dep], indices[name], Edge::Requires).unwrap();
             }
         }
-        env
+        dag
     }
 
-}
+    #[tokio::test]
+    async fn test_run_async_respects_dependencies() {
+        let home = TempDir::new("order");
+        let log = home.join("order.log");
+        let append = |name: &str| format!("sleep 0.1\necho {name} >> {}\n", log.display());
+
+        let dag = generate_test_dag(&[
+            ("c", &["b"], append("c")),
+            ("b", &["a"], append("b")),
+            ("a", &[], append("a")),
+        ]);
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["c"]), dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        assert_eq!(fs::read_to_string(&log).unwrap(), "a\nb\nc\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_writes_task_logs() {
+        let home = TempDir::new("logs");
+        let mut dag = generate_test_dag(&[("greet", &[], "echo \"$greeting\"\necho oops >&2\nexit 3\n".to_string())]);
+        dag[NodeIndex::new(0)].values.insert("greeting".to_string(), Value::Item("howdy".to_string()));
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["greet"]), dag, DEFAULT_HASH.to_string());
+        assert!(scheduler.run_async().await.is_err());
+
+        let run_dir = home.join("latest");
+        assert_eq!(fs::read_to_string(run_dir.join("greet.stdout")).unwrap(), "howdy\n");
+        assert_eq!(fs::read_to_string(run_dir.join("greet.stderr")).unwrap(), "oops\n");
+        let record: serde_json::Value = serde_json::from_str(&fs::read_to_string(run_dir.join("greet.json")).unwrap()).unwrap();
+        assert_eq!(record["status"], "failed");
+        assert_eq!(record["exit_code"], 3);
+        assert_eq!(record["env"]["greeting"], "howdy");
+        assert_eq!(record["values"]["greeting"], "howdy");
+        assert!(record["finished_at"].as_f64().unwrap() >= record["started_at"].as_f64().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_run_async_skips_up_to_date_tasks() {
+        let home = TempDir::new("incremental");
+        let input = home.join("input.txt");
+        let output = home.join("output.txt");
+        let counter = home.join("counter.log");
+        fs::write(&input, "one").unwrap();
+
+        let action = format!("echo run >> {}\ncp {} {}\n", counter.display(), input.display(), output.display());
+        let mut dag = generate_test_dag(&[("gen", &[], action)]);
+        dag[NodeIndex::new(0)].inputs = vec![input.to_string_lossy().to_string()];
+        dag[NodeIndex::new(0)].outputs = vec![output.to_string_lossy().to_string()];
+        dag[NodeIndex::new(0)].cache = false;
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["gen"]), dag, DEFAULT_HASH.to_string());
+
+        scheduler.run_async().await.unwrap();
+        scheduler.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\n", "unchanged inputs");
+
+        fs::write(&input, "two").unwrap();
+        scheduler.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\nrun\n", "changed inputs");
+
+        fs::remove_file(&output).unwrap();
+        scheduler.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\nrun\nrun\n", "missing outputs");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_restores_outputs_from_cache() {
+        let home = TempDir::new("cache");
+        let input = home.join("input.txt");
+        let output = home.join("output.txt");
+        let counter = home.join("counter.log");
+        fs::write(&input, "one").unwrap();
+
+        let action = format!("echo run >> {}\ncp {} {}\n", counter.display(), input.display(), output.display());
+        let mut dag = generate_test_dag(&[("gen", &[], action)]);
+        dag[NodeIndex::new(0)].dir = Some(home.to_path_buf());
+        dag[NodeIndex::new(0)].inputs = vec!["input.txt".to_string()];
+        dag[NodeIndex::new(0)].outputs = vec!["output.txt".to_string()];
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["gen"]), dag, DEFAULT_HASH.to_string());
+
+        scheduler.run_async().await.unwrap();
+        fs::write(&input, "two").unwrap();
+        scheduler.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\nrun\n");
+
+        // Going back to the first input restores the first output without running the task,
+        // even with the output and the stored fingerprints gone
+        fs::write(&input, "one").unwrap();
+        fs::remove_file(&output).unwrap();
+        fs::remove_dir_all(scheduler.store_dir().unwrap()).unwrap();
+        scheduler.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\nrun\n");
+        assert_eq!(fs::read_to_string(&output).unwrap(), "one");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_shares_the_remote_cache_between_checkouts() {
+        let root = TempDir::new("remote-checkouts");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api = format!("http://{}", listener.local_addr().unwrap());
+        let server = crate::cmd::remote::serve(listener, Cache::at(root.join("server")), true, Limits::default());
+        tokio::spawn(server);
+
+        // The same project checked out at two paths, each with an otto home of its own
+        let counter = root.join("counter.log");
+        let action = format!("echo run >> {}\ncp input.txt output.txt\n", counter.display());
+        let run = |checkout: &str| {
+            let project = root.join(checkout).join("project");
+            fs::create_dir_all(&project).unwrap();
+            fs::write(project.join("input.txt"), "one").unwrap();
+            let mut dag = generate_test_dag(&[("gen", &[], action.clone())]);
+            dag[NodeIndex::new(0)].dir = Some(project.clone());
+            dag[NodeIndex::new(0)].inputs = vec!["input.txt".to_string()];
+            dag[NodeIndex::new(0)].outputs = vec!["output.txt".to_string()];
+            let mut otto = generate_test_otto(&root.join(checkout).join("home"), &["gen"]);
+            otto.api = api.clone();
+            (Scheduler::new(otto, dag, DEFAULT_HASH.to_string()), project)
+        };
+
+        let (first, _) = run("a");
+        let (second, project) = run("b");
+        assert_eq!(first.fingerprints().unwrap(), second.fingerprints().unwrap());
+        first.run_async().await.unwrap();
+        second.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "run\n", "the second checkout is served by the remote cache");
+        assert_eq!(fs::read_to_string(project.join("output.txt")).unwrap(), "one");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_failed_dependency_does_not_hang() {
+        let home = TempDir::new("failed");
+        let dag = generate_test_dag(&[("a", &[], "exit 1\n".to_string()), ("b", &["a"], "true\n".to_string())]);
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["b"]), dag, DEFAULT_HASH.to_string());
+
+        let result = tokio::time::timeout(Duration::from_secs(10), scheduler.run_async()).await;
+        let err = result.expect("scheduler hung on a failed dependency").unwrap_err();
+        let Some(SchedulerError::RunFailed(summary)) = err.downcast_ref::<SchedulerError>() else {
+            panic!("expected a run summary, got: {err}");
+        };
+        assert_eq!(summary.failed(), vec!["a"]);
+        assert_eq!(summary.skipped(), vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_async_fail_fast_cancels_running_tasks() {
+        let home = TempDir::new("fail-fast");
+        let dag = generate_test_dag(&[
+            ("slow", &[], "echo started\nsleep 30\n".to_string()),
+            ("broken", &[], "sleep 0.2\nexit 1\n".to_string()),
+            ("after", &["broken"], "true\n".to_string()),
+        ]);
+        let mut otto = generate_test_otto(&home, &["*"]);
+        otto.output = OutputMode::Grouped;
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+
+        let result = tokio::time::timeout(Duration::from_secs(10), scheduler.run_async()).await;
+        let err = result.expect("fail-fast did not cancel the running task").unwrap_err();
+        let Some(SchedulerError::RunFailed(summary)) = err.downcast_ref::<SchedulerError>() else {
+            panic!("expected a run summary, got: {err}");
+        };
+        assert_eq!(summary.failed(), vec!["broken"]);
+        assert_eq!(summary.cancelled(), vec!["slow"]);
+        assert_eq!(summary.skipped(), vec!["after"]);
+
+        // The aborted task still leaves its output and a record behind
+        let run_dir = home.join("latest");
+        assert_eq!(fs::read_to_string(run_dir.join("slow.stdout")).unwrap(), "started\n");
+        let record: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(run_dir.join("slow.json")).unwrap()).unwrap();
+        assert_eq!(record["status"], "aborted");
+    }
 
+    #[tokio::test]
+    async fn test_run_async_keep_going_runs_independent_tasks() {
+        let home = TempDir::new("keep-going");
+        let dag = generate_test_dag(&[
+            ("broken", &[], "exit 1\n".to_string()),
+            ("after", &["broken"], "true\n".to_string()),
+            ("other", &[], "sleep 0.2\n".to_string()),
+            ("downstream", &["other"], "true\n".to_string()),
+        ]);
+        let mut otto = generate_test_otto(&home, &["*"]);
+        otto.keep_going = true;
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+
+        let err = scheduler.run_async().await.unwrap_err();
+        let Some(SchedulerError::RunFailed(summary)) = err.downcast_ref::<SchedulerError>() else {
+            panic!("expected a run summary, got: {err}");
+        };
+        assert_eq!(summary.failed(), vec!["broken"]);
+        assert_eq!(summary.skipped(), vec!["after"]);
+        assert_eq!(summary.succeeded(), vec!["other", "downstream"]);
+        assert!(err.to_string().contains("after (because broken failed)"));
+    }
+
+    #[tokio::test]
+    async fn test_run_async_orders_without_requiring() {
+        let home = TempDir::new("order-after");
+        let mut dag = generate_test_dag(&[
+            ("broken", &[], "sleep 0.2\nexit 1\n".to_string()),
+            ("test", &["broken"], "true\n".to_string()),
+            ("cleanup", &[], "true\n".to_string()),
+            ("lint", &[], "true\n".to_string()),
+        ]);
+        let index = |dag: &DAG<TaskSpec>, name: &str| dag.graph().node_indices().find(|i| dag[*i].name == name).unwrap();
+        let (broken, test, cleanup, lint) =
+            (index(&dag, "broken"), index(&dag, "test"), index(&dag, "cleanup"), index(&dag, "lint"));
+        dag.add_edge(broken, cleanup, Edge::OrderAfter).unwrap();
+        dag.add_edge(test, cleanup, Edge::OrderAfter).unwrap();
+        dag.add_edge(lint, cleanup, Edge::OrderAfter).unwrap();
+
+        // Being ordered after a task does not pull it into the run
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["cleanup", "test"]), dag.clone(), DEFAULT_HASH.to_string());
+        let mut selected: Vec<String> = scheduler.get_tasks_to_execute().unwrap().into_iter().collect();
+        selected.sort();
+        assert_eq!(selected, vec_of_strings!["broken", "cleanup", "test"]);
+
+        // It still waits for it, and runs when it fails, unlike a task that requires it
+        let mut otto = generate_test_otto(&home, &["cleanup", "test"]);
+        otto.keep_going = true;
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+        let err = scheduler.run_async().await.unwrap_err();
+        let Some(SchedulerError::RunFailed(summary)) = err.downcast_ref::<SchedulerError>() else {
+            panic!("expected a run summary, got: {err}");
+        };
+        assert_eq!(summary.failed(), vec!["broken"]);
+        assert_eq!(summary.skipped(), vec!["test"]);
+        assert_eq!(summary.succeeded(), vec!["cleanup"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_async_retries_failed_tasks() {
+        let home = TempDir::new("retries");
+        let counter = home.join("counter.log");
+        // Fails twice, then succeeds
+        let action = format!("echo try >> {0}\n[ $(wc -l < {0}) -ge 3 ]\n", counter.display());
+        let mut dag = generate_test_dag(&[("flaky", &[], action)]);
+        dag[NodeIndex::new(0)].retries = 2;
+        dag[NodeIndex::new(0)].retry_delay = Duration::from_millis(10);
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["flaky"]), dag, DEFAULT_HASH.to_string());
+
+        scheduler.run_async().await.unwrap();
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "try\ntry\ntry\n");
+        let record: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(home.join("latest/flaky.json")).unwrap()).unwrap();
+        assert_eq!(record["status"], "succeeded");
+        assert_eq!(record["attempts"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_async_kills_tasks_that_time_out() {
+        let home = TempDir::new("timeout");
+        let marker = home.join("survived");
+        // The background sleep is in the task's process group and must be killed along with it
+        let action = format!("(sleep 1; touch {}) &\necho started\nsleep 30\n", marker.display());
+        let mut dag = generate_test_dag(&[("hang", &[], action)]);
+        dag[NodeIndex::new(0)].timeout = Some(Duration::from_millis(200));
+        dag[NodeIndex::new(0)].retries = 1;
+        dag[NodeIndex::new(0)].retry_delay = Duration::from_millis(10);
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["hang"]), dag, DEFAULT_HASH.to_string());
+
+        let result = tokio::time::timeout(Duration::from_secs(10), scheduler.run_async()).await;
+        let err = result.expect("the timeout was not enforced").unwrap_err();
+        let Some(SchedulerError::RunFailed(summary)) = err.downcast_ref::<SchedulerError>() else {
+            panic!("expected a run summary, got: {err}");
+        };
+        assert_eq!(summary.failed(), vec!["hang"]);
+        assert_eq!(summary.retried(), vec![("hang", 2)]);
+        assert!(err.to_string().contains("timed out after 200ms"));
+        assert_eq!(fs::read_to_string(home.join("latest/hang.stdout")).unwrap(), "started\nstarted\n");
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists(), "the task's process group was not killed");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_forwards_interrupts_to_running_tasks() {
+        let home = TempDir::new("interrupt");
+        let mut dag = generate_test_dag(&[
+            ("polite", &[], "trap 'echo interrupted; exit 1' INT\nsleep 30\n".to_string()),
+            ("stubborn", &[], "trap '' INT\nsleep 30\n".to_string()),
+            ("later", &["polite"], "true\n".to_string()),
+            ("flaky", &[], "echo flaky\nexit 1\n".to_string()),
+        ]);
+        dag[NodeIndex::new(3)].retries = 3;
+        dag[NodeIndex::new(3)].retry_delay = Duration::from_secs(30);
+        let mut otto = generate_test_otto(&home, &["*"]);
+        otto.grace_period = Duration::from_millis(300);
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+
+        let (sender, interrupt) = watch::channel(None);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            sender.send(Some(libc::SIGINT)).unwrap();
+            // Keep the channel open until the run is over
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
+        let result = tokio::time::timeout(Duration::from_secs(10), scheduler.run_until_interrupted(interrupt)).await;
+        let err = result.expect("interrupted tasks were not stopped").unwrap_err();
+        let Some(SchedulerError::Cancelled(summary)) = err.downcast_ref::<SchedulerError>() else {
+            panic!("expected a cancelled run, got: {err}");
+        };
+        assert_eq!(summary.cancelled(), vec!["polite", "stubborn", "flaky"]);
+        assert_eq!(summary.skipped(), vec!["later"]);
+
+        // The polite task handled the signal itself, the stubborn one was killed after the grace period
+        let run_dir = home.join("latest");
+        assert_eq!(fs::read_to_string(run_dir.join("polite.stdout")).unwrap(), "interrupted\n");
+        let record: serde_json::Value = serde_json::from_str(&fs::read_to_string(run_dir.join(".run.json")).unwrap()).unwrap();
+        assert_eq!(record["status"], "cancelled");
+        assert_eq!(record["signal"], "SIGINT");
+        assert_eq!(record["tasks"]["stubborn"], "cancelled");
+        assert_eq!(record["tasks"]["later"], "skipped");
+        let record = |name: &str| -> serde_json::Value {
+            serde_json::from_str(&fs::read_to_string(run_dir.join(format!("{name}.json"))).unwrap()).unwrap()
+        };
+        assert_eq!(record("polite")["status"], "cancelled");
+        assert_eq!(record("stubborn")["status"], "cancelled");
+
+        // The flaky task was interrupted waiting to be retried, and cleaned up all the same
+        assert_eq!(record("flaky")["status"], "cancelled");
+        assert_eq!(record("flaky")["attempts"], 1);
+        assert_eq!(fs::read_to_string(run_dir.join("flaky.stdout")).unwrap(), "flaky\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_honors_shebangs_and_shells() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let home = TempDir::new("shebang");
+        let mut dag = generate_test_dag(&[
+            ("shebang", &[], "#!/bin/bash\nwords=(a b c)\necho ${#words[@]}\n".to_string()),
+            ("strict", &[], "[[ -o nounset ]] && echo strict\n".to_string()),
+        ]);
+        dag[NodeIndex::new(1)].shell = Some("bash -eu".to_string());
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["*"]), dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let run_dir = home.join("latest");
+        assert_eq!(fs::read_to_string(run_dir.join("shebang.stdout")).unwrap(), "3\n");
+        assert_eq!(fs::read_to_string(run_dir.join("strict.stdout")).unwrap(), "strict\n");
+        for script in ["shebang.sh", "strict.sh"] {
+            let mode = fs::metadata(run_dir.join(script)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o111, 0o111, "{script} is not executable");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_async_keeps_a_task_named_run_apart_from_the_run_record() {
+        let home = TempDir::new("run-record");
+        let dag = generate_test_dag(&[("run", &[], "echo running\n".to_string())]);
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["run"]), dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let read = |file: &str| -> serde_json::Value {
+            serde_json::from_str(&fs::read_to_string(home.join("latest").join(file)).unwrap()).unwrap()
+        };
+        assert_eq!(read("run.json")["name"], "run");
+        assert_eq!(read(".run.json")["tasks"]["run"], "succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_gives_tasks_no_stdin() {
+        let home = TempDir::new("stdin");
+        let dag = generate_test_dag(&[("ask", &[], "if read answer; then echo \"read $answer\"; else echo eof; fi\n".to_string())]);
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["ask"]), dag, DEFAULT_HASH.to_string());
+        tokio::time::timeout(Duration::from_secs(10), scheduler.run_async()).await.unwrap().unwrap();
+        assert_eq!(fs::read_to_string(home.join("latest/ask.stdout")).unwrap(), "eof\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_executes_cmd_without_a_shell() {
+        let home = TempDir::new("cmd");
+        let mut dag = generate_test_dag(&[("print", &[], String::new())]);
+        let task = &mut dag[NodeIndex::new(0)];
+        task.cmd = vec_of_strings!["printf", "%s\\n", "${message}", "${files}"];
+        task.values.insert("message".to_string(), Value::Item("it's $HOME; exit 1".to_string()));
+        task.values.insert("files".to_string(), Value::List(vec_of_strings!["a b", "c"]));
+        assert_eq!(
+            Scheduler::describe(task).unwrap(),
+            r"print: printf '%s\n' 'it'\''s $HOME; exit 1' 'a b' c"
+        );
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["print"]), dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let run_dir = home.join("latest");
+        assert_eq!(fs::read_to_string(run_dir.join("print.stdout")).unwrap(), "it's $HOME; exit 1\na b\nc\n");
+        assert!(!run_dir.join("print.sh").exists(), "no script is written for a cmd");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_runs_tasks_in_their_directory() {
+        let home = TempDir::new("dir");
+        let project = home.join("project");
+        fs::create_dir_all(project.join("sub")).unwrap();
+        let mut dag = generate_test_dag(&[
+            ("here", &[], "pwd\necho \"$OTTO_INVOCATION_DIR\"\n".to_string()),
+            ("there", &[], "pwd\n".to_string()),
+        ]);
+        dag[NodeIndex::new(0)].dir = Some(project.clone());
+        dag[NodeIndex::new(1)].dir = Some(project.join("sub"));
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["*"]), dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let run_dir = home.join("latest");
+        let invocation_dir = env::current_dir().unwrap();
+        assert_eq!(
+            fs::read_to_string(run_dir.join("here.stdout")).unwrap(),
+            format!("{}\n{}\n", project.display(), invocation_dir.display())
+        );
+        assert_eq!(fs::read_to_string(run_dir.join("there.stdout")).unwrap(), format!("{}\n", project.join("sub").display()));
+    }
+
+    #[tokio::test]
+    async fn test_run_async_uses_the_given_invocation_dir() {
+        let home = TempDir::new("invocation-dir");
+        let elsewhere = home.join("elsewhere");
+        fs::create_dir_all(&elsewhere).unwrap();
+        let dag = generate_test_dag(&[("here", &[], "pwd\necho \"$OTTO_INVOCATION_DIR\"\n".to_string())]);
+        let mut otto = generate_test_otto(&home, &["here"]);
+        otto.invocation_dir = Some(elsewhere.clone());
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let stdout = fs::read_to_string(home.join("latest/here.stdout")).unwrap();
+        assert_eq!(stdout, format!("{}\n{}\n", elsewhere.display(), elsewhere.display()));
+    }
+
+    #[tokio::test]
+    async fn test_run_async_exports_otto_variables() {
+        let home = TempDir::new("otto-env");
+        let dag = generate_test_dag(&[("show", &[], "env | grep ^OTTO_ | sort\n".to_string())]);
+        let mut scheduler = Scheduler::new(generate_test_otto(&home, &["show"]), dag, DEFAULT_HASH.to_string());
+        scheduler.ottofile = Some(home.join("project/otto.yml"));
+        scheduler.run_async().await.unwrap();
+
+        let run_dir = home.join(scheduler.timestamp.to_string());
+        let stdout = fs::read_to_string(run_dir.join("show.stdout")).unwrap();
+        let env: HashMap<&str, &str> = stdout.lines().filter_map(|line| line.split_once('=')).collect();
+        assert_eq!(env["OTTO_TASK"], "show");
+        assert_eq!(env["OTTO_RUN_ID"], scheduler.timestamp.to_string());
+        assert_eq!(env["OTTO_RUN_DIR"], run_dir.to_string_lossy());
+        assert_eq!(env["OTTO_HOME"], home.to_string_lossy());
+        assert_eq!(env["OTTO_OTTOFILE"], home.join("project/otto.yml").to_string_lossy());
+        assert_eq!(env["OTTO_PROJECT_DIR"], home.join("project").to_string_lossy());
+        assert_eq!(env["OTTO_INVOCATION_DIR"], env::current_dir().unwrap().to_string_lossy());
+        assert_eq!(env["OTTO_JOBS"], "4");
+        assert_eq!(env["OTTO_CONFIG_HASH"], DEFAULT_HASH);
+        assert_eq!(env["OTTO_TASK_HASH"].len(), 64);
+        assert_eq!(env["OTTO_ARGS"], "");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_passes_arguments_through() {
+        let home = TempDir::new("passthrough");
+        let mut dag = generate_test_dag(&[
+            ("script", &[], "printf '%s\\n' \"$@\" \"$OTTO_ARGS\"\n".to_string()),
+            ("print", &[], String::new()),
+        ]);
+        dag[NodeIndex::new(0)].args = vec_of_strings!["--nocapture", "it's"];
+        dag[NodeIndex::new(1)].cmd = vec_of_strings!["printf", "%s\\n"];
+        dag[NodeIndex::new(1)].args = vec_of_strings!["a b", "c"];
+        assert_eq!(Scheduler::describe(&dag[NodeIndex::new(1)]).unwrap(), r"print: printf '%s\n' 'a b' c");
+        let scheduler = Scheduler::new(generate_test_otto(&home, &["*"]), dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let run_dir = home.join("latest");
+        assert_eq!(
+            fs::read_to_string(run_dir.join("script.stdout")).unwrap(),
+            "--nocapture\nit's\n--nocapture 'it'\\''s'\n"
+        );
+        assert_eq!(fs::read_to_string(run_dir.join("print.stdout")).unwrap(), "a b\nc\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_async_exports_lists_and_dicts() {
+        let home = TempDir::new("encoded");
+        let action = "echo \"$files|$files_1|$files_COUNT|$files_JSON\"\n\
+                      echo \"$conn_db_host|$conn_KEYS|$conn_JSON\"\n";
+        let mut dag = generate_test_dag(&[("show", &[], action.to_string()), ("clash", &[], "true\n".to_string())]);
+        let conn = HashMap::from([("db-host".to_string(), "localhost".to_string())]);
+        dag[NodeIndex::new(0)].values.insert("files".to_string(), Value::List(vec_of_strings!["a", "b c"]));
+        dag[NodeIndex::new(0)].values.insert("conn".to_string(), Value::Dict(conn));
+        dag[NodeIndex::new(1)].values.insert("files".to_string(), Value::List(vec_of_strings!["a"]));
+        dag[NodeIndex::new(1)].values.insert("files_0".to_string(), Value::Item("b".to_string()));
+        let mut otto = generate_test_otto(&home, &["*"]);
+        otto.keep_going = true;
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+        let err = scheduler.run_async().await.unwrap_err().to_string();
+        assert!(err.contains("Task clash: files and files_0 would both set the environment variable files_0"), "{err}");
+
+        let run_dir = home.join("latest");
+        assert_eq!(
+            fs::read_to_string(run_dir.join("show.stdout")).unwrap(),
+            "a b c|b c|2|[\"a\",\"b c\"]\nlocalhost|db-host|{\"db-host\":\"localhost\"}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_async_keeps_node_names_out_of_paths() {
+        let home = TempDir::new("file-names");
+        let mut dag = generate_test_dag(&[("build[dir=../a/b]", &[], "echo built\ntouch out\n".to_string())]);
+        dag[NodeIndex::new(0)].outputs = vec!["out".to_string()];
+        dag[NodeIndex::new(0)].dir = Some(home.to_path_buf());
+        let otto = generate_test_otto(&home, &["build[dir=../a/b]"]);
+        let scheduler = Scheduler::new(otto, dag, DEFAULT_HASH.to_string());
+        scheduler.run_async().await.unwrap();
+
+        let run_dir = home.join("latest");
+        assert_eq!(fs::read_to_string(run_dir.join("build[dir=..%2Fa%2Fb].stdout")).unwrap(), "built\n");
+        assert!(run_dir.join("build[dir=..%2Fa%2Fb].json").exists());
+        let stored: Vec<String> = fs::read_dir(scheduler.store_dir().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(stored.iter().any(|name| name.starts_with("build[dir=..%2Fa%2Fb]-") && name.ends_with(".fingerprint")));
+    }
+}
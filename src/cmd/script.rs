@@ -0,0 +1,119 @@
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use tokio::process::{Child, Command};
+
+/// The interpreter for actions without a shebang, unless the Ottofile sets another.
+pub const DEFAULT_SHELL: &str = "sh";
+
+/// The interpreter line of `action`, without the `#!`, if it starts with one.
+#[must_use]
+pub fn shebang(action: &str) -> Option<&str> {
+    action.strip_prefix("#!").and_then(|rest| rest.lines().next()).map(str::trim)
+}
+
+/// The file extension scripts for `interpreter` conventionally have, e.g. `py` for `python3 -u`
+/// or `/usr/bin/env node`. Unknown interpreters get none.
+#[must_use]
+pub fn extension(interpreter: &str) -> Option<&'static str> {
+    let mut words = interpreter.split_whitespace();
+    let mut program = words.next()?;
+    if program.rsplit('/').next() == Some("env") {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    let name = program.rsplit('/').next()?;
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    match name {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" => Some("sh"),
+        "python" | "pypy" => Some("py"),
+        "node" | "nodejs" => Some("js"),
+        "ruby" => Some("rb"),
+        "perl" => Some("pl"),
+        "php" => Some("php"),
+        "lua" => Some("lua"),
+        "fish" => Some("fish"),
+        "pwsh" | "powershell" => Some("ps1"),
+        _ => None,
+    }
+}
+
+/// Writes `action` as an executable script for `name` into `dir`, with the extension of the
+/// interpreter that runs it, and returns the command that runs it.
+///
+/// With a `shell`, such as `bash -euo pipefail`, the script is passed to it as the last argument;
+/// it is split on whitespace. Without one the script is executed directly, through its shebang.
+///
+/// # Errors
+///
+/// This function will return an error if the script cannot be written, or if there is neither a
+/// shell nor a shebang to run it with.
+pub async fn prepare(name: &str, action: &str, shell: Option<&str>, dir: &Path) -> Result<Command> {
+    let interpreter = shell
+        .or_else(|| shebang(action))
+        .filter(|interpreter| !interpreter.is_empty())
+        .ok_or_else(|| eyre!("Task {} has no shell and its action has no shebang", name))?;
+    let script = match extension(interpreter) {
+        Some(extension) => dir.join(format!("{name}.{extension}")),
+        None => dir.join(name),
+    };
+
+    // Write the action to a file
+    tokio::fs::write(&script, action).await.map_err(|e| eyre!("Failed to write action to file: {}", e))?;
+    tokio::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).await?;
+
+    let command = match shell {
+        Some(shell) => {
+            let mut words = shell.split_whitespace();
+            let mut command = Command::new(words.next().unwrap_or_default());
+            command.args(words).arg(&script);
+            command
+        }
+        None => Command::new(&script),
+    };
+    Ok(command)
+}
+
+/// Spawns `command`. A script that was just written can still be open for writing in a process
+/// another task is forking at the same moment, which makes executing it fail with ETXTBSY for an
+/// instant, so that error is retried.
+///
+/// # Errors
+///
+/// This function will return an error if the command cannot be spawned.
+pub async fn spawn(command: &mut Command) -> io::Result<Child> {
+    let mut attempts = 0;
+    loop {
+        match command.spawn() {
+            Err(err) if err.raw_os_error() == Some(libc::ETXTBSY) && attempts < 10 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shebang() {
+        assert_eq!(shebang("#!/bin/bash\necho hi\n"), Some("/bin/bash"));
+        assert_eq!(shebang("#! /usr/bin/env python3 \nprint(1)\n"), Some("/usr/bin/env python3"));
+        assert_eq!(shebang("echo '#!/bin/bash'\n"), None);
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("sh"), Some("sh"));
+        assert_eq!(extension("bash -euo pipefail"), Some("sh"));
+        assert_eq!(extension("/usr/bin/python3.11"), Some("py"));
+        assert_eq!(extension("/usr/bin/env -S node --harmony"), Some("js"));
+        assert_eq!(extension("ruby"), Some("rb"));
+        assert_eq!(extension("awk -f"), None);
+    }
+}
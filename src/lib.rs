@@ -2,6 +2,8 @@ pub mod cfg;
 #[macro_use]
 pub mod cli;
 pub mod cmd;
+#[cfg(test)]
+mod testing;
 
 #[cfg(test)]
 mod tests {
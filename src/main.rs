@@ -1,9 +1,12 @@
 //#![allow(unused_imports, unused_variables, unused_attributes, unused_mut, dead_code)]
 
 use std::env;
+use std::path::Path;
 use eyre::Report;
 
-use otto::cli::parse::Parser;
+use otto::cli::error::OttoParseError;
+use otto::cli::parse::{Builtin, Parser};
+use otto::cmd::{cache, remote};
 use otto::cmd::scheduler::Scheduler;
 
 #[tokio::main]
@@ -11,9 +14,25 @@ async fn main() -> Result<(), Report> {
     let args: Vec<String> = env::args().collect();
     let mut parser = Parser::new(args)?;
 
-    let (otto, jobs, hash) = parser.parse()?;
-    let scheduler = Scheduler::new(otto, jobs, hash);
-    scheduler.run_async().await?;
+    let (otto, jobs, hash) = match parser.parse() {
+        Ok(parsed) => parsed,
+        // Without tasks the help has been shown, and otto exits successfully as it always has
+        Err(err) if matches!(err.downcast_ref(), Some(OttoParseError::NoTasks)) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let mut scheduler = Scheduler::new(otto, jobs, hash);
+    scheduler.ottofile = parser.ottofile().map(Path::to_path_buf);
+    match parser.builtin() {
+        Some(Builtin::Hash) => {
+            for (name, fingerprint) in scheduler.fingerprints()? {
+                println!("{fingerprint}  {name}");
+            }
+        }
+        Some(Builtin::Cache(args)) => cache::run(&scheduler.home_dir()?, args)?,
+        Some(Builtin::CacheServer(args)) => remote::run(&scheduler.home_dir()?, args).await?,
+        None if scheduler.otto.dry_run => scheduler.dry_run()?,
+        None => scheduler.run_async().await?,
+    }
 
     Ok(())
 }
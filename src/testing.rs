@@ -0,0 +1,93 @@
+//! Fixtures shared by the tests of every module.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+
+use crate::cfg::otto::Otto;
+use crate::cli::parse::{Parser, TaskSpec, DAG};
+
+/// A fresh directory under the system temp directory that is removed again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `otto-test-<pid>-<name>`, clearing out whatever an earlier run left there.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("otto-test-{}-{}", std::process::id(), name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Like [`TempDir::new`], with the directories `dirs` created inside it.
+    pub fn with_dirs(name: &str, dirs: &[&str]) -> Self {
+        let dir = Self::new(name);
+        for sub in dirs {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        dir
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An Ottofile in a temp directory of its own, to parse command lines against.
+pub struct Ottofile {
+    dir: TempDir,
+    path: PathBuf,
+}
+
+impl Ottofile {
+    /// Writes `yaml` to `otto.yml` in a fresh [`TempDir`] called `name`.
+    pub fn new(name: &str, yaml: &str) -> Self {
+        let dir = TempDir::new(name);
+        let path = dir.join("otto.yml");
+        let ottofile = Self { dir, path };
+        ottofile.write(yaml);
+        ottofile
+    }
+
+    /// Replaces the content of the Ottofile with `yaml`.
+    pub fn write(&self, yaml: &str) {
+        fs::write(&self.path, yaml).unwrap();
+    }
+
+    /// The directory holding the Ottofile.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A parser for `otto --ottofile <this Ottofile> <args>`.
+    pub fn parser(&self, args: &[&str]) -> Parser {
+        let mut argv = vec!["otto".to_string(), "--ottofile".to_string(), self.path.to_string_lossy().to_string()];
+        argv.extend(args.iter().map(ToString::to_string));
+        Parser::new(argv).unwrap()
+    }
+
+    /// Parses `otto --ottofile <this Ottofile> <args>`.
+    pub fn parse(&self, args: &[&str]) -> Result<(Otto, DAG<TaskSpec>, String)> {
+        self.parser(args).parse()
+    }
+}